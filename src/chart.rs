@@ -0,0 +1,159 @@
+//! Minimal SVG line charts, without a plotting dependency. Both axes start at 0 and are linear.
+
+use std::fmt::Write;
+
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 420.0;
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 220.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 50.0;
+const TICKS: usize = 5;
+
+const COLORS: [&str; 15] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf", "#393b79", "#637939", "#8c6d31", "#843c39", "#7b4173",
+];
+
+/// A chart with one line per series, the legend is placed right of the plot.
+pub fn line_chart(title: &str, x_label: &str, y_label: &str, series: &[Series]) -> String {
+    let points = || series.iter().flat_map(|series| series.points.iter());
+    let x_step = tick_step(points().map(|point| point.0).fold(0.0, f64::max));
+    let y_step = tick_step(points().map(|point| point.1).fold(0.0, f64::max));
+    let (x_max, y_max) = (x_step * TICKS as f64, y_step * TICKS as f64);
+    let (x_decimals, y_decimals) = (decimals(x_step), decimals(y_step));
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let x = |value: f64| LEFT + value / x_max * plot_width;
+    let y = |value: f64| TOP + plot_height - value / y_max * plot_height;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{}" y="20" text-anchor="middle" font-size="14">{}</text>"#,
+        LEFT + plot_width / 2.0,
+        escape(title)
+    )
+    .unwrap();
+    for tick in 0..=TICKS {
+        let (x_value, y_value) = (x_step * tick as f64, y_step * tick as f64);
+        writeln!(
+            svg,
+            r##"<line x1="{left}" y1="{y:.1}" x2="{right}" y2="{y:.1}" stroke="#ddd"/><text x="{label}" y="{y:.1}" text-anchor="end" dy="4">{y_value:.y_decimals$}</text>"##,
+            left = LEFT,
+            right = LEFT + plot_width,
+            label = LEFT - 6.0,
+            y = y(y_value),
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r##"<line x1="{x:.1}" y1="{top}" x2="{x:.1}" y2="{bottom}" stroke="#ddd"/><text x="{x:.1}" y="{label}" text-anchor="middle">{x_value:.x_decimals$}</text>"##,
+            top = TOP,
+            bottom = TOP + plot_height,
+            label = TOP + plot_height + 16.0,
+            x = x(x_value),
+        )
+        .unwrap();
+    }
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        LEFT + plot_width / 2.0,
+        HEIGHT - 10.0,
+        escape(x_label)
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="20" y="{0}" text-anchor="middle" transform="rotate(-90 20 {0})">{1}</text>"#,
+        TOP + plot_height / 2.0,
+        escape(y_label)
+    )
+    .unwrap();
+    for (index, series) in series.iter().enumerate() {
+        let color = COLORS[index % COLORS.len()];
+        let points = series
+            .points
+            .iter()
+            .map(|point| format!("{:.1},{:.1}", x(point.0), y(point.1)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            svg,
+            r#"<polyline points="{points}" fill="none" stroke="{color}" stroke-width="1.5"/>"#
+        )
+        .unwrap();
+        let legend_y = TOP + 16.0 * index as f64;
+        writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{1}" x2="{2}" y2="{1}" stroke="{color}" stroke-width="3"/><text x="{3}" y="{1}" dy="4">{4}</text>"#,
+            WIDTH - RIGHT + 20.0,
+            legend_y,
+            WIDTH - RIGHT + 40.0,
+            WIDTH - RIGHT + 46.0,
+            escape(&series.name)
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Places the charts below each other in one document.
+pub fn stack(charts: &[String]) -> String {
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{}">"#,
+        HEIGHT * charts.len() as f64
+    )
+    .unwrap();
+    for (index, chart) in charts.iter().enumerate() {
+        writeln!(
+            svg,
+            r#"<g transform="translate(0 {})">"#,
+            HEIGHT * index as f64
+        )
+        .unwrap();
+        svg.push_str(chart);
+        svg.push_str("</g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// 1, 2 or 5 times a power of ten, so that `TICKS` steps cover `max`.
+fn tick_step(max: f64) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let raw = max / TICKS as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Decimals needed to print multiples of `step` exactly.
+fn decimals(step: f64) -> usize {
+    (-step.log10().floor()).max(0.0) as usize
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,296 @@
+//! Log-linear histogram as used by Circonus / OpenHistogram (libcircllhist).
+//!
+//! A bucket is identified by two significant decimal digits `val` (10..=99, signed for negative
+//! values) and a decimal exponent `exp` (-128..=127). Bucket `(val, exp)` covers
+//! `[val * 10^(exp-1), (val + 1) * 10^(exp-1))`, so every decade is split into 90 buckets of
+//! equal width. This covers floats, negative values and a range of 10^-128 to 10^128 with a
+//! relative error of at most 10%, usually far less.
+//!
+//! The binary serialization is the one of libcircllhist: the number of buckets as big-endian
+//! `u16`, followed by `val: i8`, `exp: i8`, the number of count bytes minus one and the count in
+//! big-endian for every bucket.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{put_varint, put_zigzag, DecodeError, Reader};
+use crate::heap_size::HeapSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Bucket {
+    val: i8,
+    exp: i8,
+}
+
+impl Bucket {
+    const ZERO: Bucket = Bucket { val: 0, exp: 0 };
+
+    /// Returns `None` for NaN, infinite values and values with an absolute value of 10^128 or
+    /// more. Values too small to be represented end up in the zero bucket.
+    fn from_value(value: f64) -> Option<Bucket> {
+        if !value.is_finite() {
+            return None;
+        }
+        let abs = value.abs();
+        if abs < 1e-128 {
+            return Some(Bucket::ZERO);
+        }
+        let mut exp = abs.log10().floor() as i32;
+        let mut val = (abs / 10f64.powi(exp - 1)).floor() as i32;
+        // log10 and the division are not exact at the decade boundaries.
+        if val < 10 {
+            exp -= 1;
+            val = (abs / 10f64.powi(exp - 1)).floor() as i32;
+        } else if val > 99 {
+            exp += 1;
+            val = (abs / 10f64.powi(exp - 1)).floor() as i32;
+        }
+        let val = val.clamp(10, 99);
+        if exp > i8::MAX as i32 {
+            return None;
+        }
+        if exp < i8::MIN as i32 {
+            return Some(Bucket::ZERO);
+        }
+        let val = if value < 0.0 { -val } else { val };
+        Some(Bucket {
+            val: val as i8,
+            exp: exp as i8,
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        *self == Bucket::ZERO || (10..=99).contains(&self.val.unsigned_abs())
+    }
+
+    /// Position of the bucket on the number line, used to keep the buckets sorted.
+    fn ordinal(&self) -> i32 {
+        if self.val == 0 {
+            return 0;
+        }
+        let magnitude =
+            1 + (self.exp as i32 - i8::MIN as i32) * 90 + (self.val.unsigned_abs() as i32 - 10);
+        if self.val < 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    fn from_ordinal(ordinal: i32) -> Option<Bucket> {
+        if ordinal == 0 {
+            return Some(Bucket::ZERO);
+        }
+        let magnitude = ordinal.abs() - 1;
+        let exp = magnitude / 90 + i8::MIN as i32;
+        let val = magnitude % 90 + 10;
+        if exp > i8::MAX as i32 {
+            return None;
+        }
+        let val = if ordinal < 0 { -val } else { val };
+        Some(Bucket {
+            val: val as i8,
+            exp: exp as i8,
+        })
+    }
+
+    fn width(&self) -> f64 {
+        if self.val == 0 {
+            return 0.0;
+        }
+        10f64.powi(self.exp as i32 - 1)
+    }
+
+    /// The smaller edge of the bucket.
+    fn lower(&self) -> f64 {
+        if self.val < 0 {
+            (self.val as f64 - 1.0) * self.width()
+        } else {
+            self.val as f64 * self.width()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    UnexpectedEof,
+    InvalidBucket { val: i8, exp: i8 },
+    InvalidCountSize(u8),
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeserializeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            DeserializeError::InvalidBucket { val, exp } => {
+                write!(f, "invalid bucket val={} exp={}", val, exp)
+            }
+            DeserializeError::InvalidCountSize(size) => {
+                write!(f, "invalid count size {}", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Circllhist {
+    /// Sorted by `Bucket::ordinal`.
+    buckets: Vec<(Bucket, u64)>,
+    count: u64,
+}
+
+impl Circllhist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Values that can't be represented (NaN, infinite, >= 10^128) are dropped.
+    pub fn insert(&mut self, value: f64) {
+        if let Some(bucket) = Bucket::from_value(value) {
+            self.add_to_bucket(bucket, 1);
+        }
+    }
+
+    fn add_to_bucket(&mut self, bucket: Bucket, count: u64) {
+        match self
+            .buckets
+            .binary_search_by_key(&bucket.ordinal(), |(bucket, _)| bucket.ordinal())
+        {
+            Ok(pos) => self.buckets[pos].1 += count,
+            Err(pos) => self.buckets.insert(pos, (bucket, count)),
+        }
+        self.count += count;
+    }
+
+    pub fn merge(&mut self, other: &Circllhist) {
+        let mut merged = Vec::with_capacity(self.buckets.len().max(other.buckets.len()));
+        let mut left = self.buckets.iter().peekable();
+        let mut right = other.buckets.iter().peekable();
+        loop {
+            let ordinals = (
+                left.peek().map(|(bucket, _)| bucket.ordinal()),
+                right.peek().map(|(bucket, _)| bucket.ordinal()),
+            );
+            let next = match ordinals {
+                (Some(l), Some(r)) if l < r => *left.next().unwrap(),
+                (Some(l), Some(r)) if l > r => *right.next().unwrap(),
+                (Some(_), Some(_)) => {
+                    let (bucket, count) = *left.next().unwrap();
+                    (bucket, count + right.next().unwrap().1)
+                }
+                (Some(_), None) => *left.next().unwrap(),
+                (None, Some(_)) => *right.next().unwrap(),
+                (None, None) => break,
+            };
+            merged.push(next);
+        }
+        self.buckets = merged;
+        self.count += other.count;
+    }
+
+    /// Approximates the quantile like `hist_approx_quantile` of libcircllhist: the bucket is
+    /// located by rank and the value is interpolated linearly within the bucket.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let rank = q * self.count as f64;
+        let mut seen = 0u64;
+        for (bucket, count) in self.buckets.iter() {
+            if (seen + count) as f64 >= rank {
+                let fraction = (rank - seen as f64) / *count as f64;
+                return Some(bucket.lower() + bucket.width() * fraction);
+            }
+            seen += count;
+        }
+        self.buckets
+            .last()
+            .map(|(bucket, _)| bucket.lower() + bucket.width())
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::vec(&self.buckets)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(2 + self.buckets.len() * 4);
+        out.extend_from_slice(&(self.buckets.len() as u16).to_be_bytes());
+        for (bucket, count) in self.buckets.iter() {
+            out.push(bucket.val as u8);
+            out.push(bucket.exp as u8);
+            let num_bytes = (8 - count.leading_zeros() as usize / 8).max(1);
+            out.push(num_bytes as u8 - 1);
+            out.extend_from_slice(&count.to_be_bytes()[8 - num_bytes..]);
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DeserializeError> {
+        let mut data = data;
+        let num_buckets = u16::from_be_bytes(take(&mut data, 2)?.try_into().unwrap());
+        let mut histogram = Circllhist::new();
+        for _ in 0..num_buckets {
+            let header = take(&mut data, 3)?;
+            let bucket = Bucket {
+                val: header[0] as i8,
+                exp: header[1] as i8,
+            };
+            if !bucket.is_valid() {
+                return Err(DeserializeError::InvalidBucket {
+                    val: bucket.val,
+                    exp: bucket.exp,
+                });
+            }
+            let num_bytes = header[2] as usize + 1;
+            if num_bytes > 8 {
+                return Err(DeserializeError::InvalidCountSize(header[2]));
+            }
+            let count = take(&mut data, num_bytes)?
+                .iter()
+                .fold(0u64, |acc, byte| acc << 8 | *byte as u64);
+            histogram.add_to_bucket(bucket, count);
+        }
+        Ok(histogram)
+    }
+
+    /// Number of buckets, the bucket ordinals as zigzag encoded deltas and the counts, all as
+    /// varints. Neighbouring buckets have consecutive ordinals, so a bucket usually takes two or
+    /// three bytes.
+    pub fn serialize_varint(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varint(&mut out, self.buckets.len() as u64);
+        let mut previous = 0;
+        for (bucket, count) in self.buckets.iter() {
+            put_zigzag(&mut out, (bucket.ordinal() - previous) as i64);
+            put_varint(&mut out, *count);
+            previous = bucket.ordinal();
+        }
+        out
+    }
+
+    pub fn deserialize_varint(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let mut histogram = Circllhist::new();
+        let mut ordinal = 0i64;
+        for _ in 0..reader.varint()? {
+            ordinal += reader.zigzag()?;
+            let bucket = i32::try_from(ordinal)
+                .ok()
+                .and_then(Bucket::from_ordinal)
+                .ok_or(DecodeError::Invalid("bucket"))?;
+            histogram.add_to_bucket(bucket, reader.varint()?);
+        }
+        reader.finish()?;
+        Ok(histogram)
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], DeserializeError> {
+    if data.len() < len {
+        return Err(DeserializeError::UnexpectedEof);
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
@@ -0,0 +1,198 @@
+//! Classic histogram with fixed bucket boundaries, as used by Prometheus (`le` buckets) and many
+//! legacy metric systems.
+//!
+//! Each bucket counts the values `<= upper bound`, an implicit `+Inf` bucket collects the rest.
+//! Quantile estimation follows `histogram_quantile` in PromQL for classic histograms, which
+//! interpolates linearly within a bucket.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{put_sorted_f64s, put_varint, DecodeError, Reader};
+use crate::heap_size::HeapSize;
+
+/// `DefBuckets` of the Prometheus client libraries, tailored to response times in seconds.
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    BucketsMismatch,
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::BucketsMismatch => write!(f, "bucket boundaries differ"),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassicHistogram {
+    /// Sorted upper bounds, without the implicit `+Inf` bucket.
+    bounds: Vec<f64>,
+    /// Non-cumulative counts, one more than `bounds` for the `+Inf` bucket.
+    counts: Vec<u64>,
+    count: u64,
+}
+
+impl ClassicHistogram {
+    pub fn new(mut bounds: Vec<f64>) -> Self {
+        assert!(
+            bounds.iter().all(|bound| bound.is_finite()),
+            "bucket boundaries must be finite"
+        );
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self {
+            bounds,
+            counts,
+            count: 0,
+        }
+    }
+
+    /// `count` buckets, the first upper bound is `start`, every following one is `width` larger.
+    pub fn linear(start: f64, width: f64, count: usize) -> Self {
+        assert!(width > 0.0, "width must be positive");
+        Self::new((0..count).map(|i| start + width * i as f64).collect())
+    }
+
+    /// `count` buckets, the first upper bound is `start`, every following one is `factor` times
+    /// larger.
+    pub fn exponential(start: f64, factor: f64, count: usize) -> Self {
+        assert!(start > 0.0, "start must be positive");
+        assert!(factor > 1.0, "factor must be greater than 1");
+        Self::new((0..count).map(|i| start * factor.powi(i as i32)).collect())
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        // First bucket with `value <= bound`, or the `+Inf` bucket.
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[index] += 1;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &ClassicHistogram) -> Result<(), MergeError> {
+        if self.bounds != other.bounds {
+            return Err(MergeError::BucketsMismatch);
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        Ok(())
+    }
+
+    /// Estimates the quantile like PromQL's `histogram_quantile` on classic buckets.
+    ///
+    /// The value is interpolated linearly between the lower and upper bound of the bucket. The
+    /// lower bound of the first bucket is assumed to be 0 if its upper bound is positive. If the
+    /// quantile falls into the `+Inf` bucket the highest finite upper bound is returned.
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 || q.is_nan() || self.bounds.is_empty() {
+            return f64::NAN;
+        }
+        if q < 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if q > 1.0 {
+            return f64::INFINITY;
+        }
+        let rank = q * self.count as f64;
+        let mut seen = 0u64;
+        let mut index = self.counts.len() - 1;
+        for (bucket_index, count) in self.counts.iter().enumerate() {
+            if *count > 0 && (seen + count) as f64 >= rank {
+                index = bucket_index;
+                break;
+            }
+            seen += count;
+        }
+        if index == self.bounds.len() {
+            return self.bounds[self.bounds.len() - 1];
+        }
+        if index == 0 && self.bounds[0] <= 0.0 {
+            return self.bounds[0];
+        }
+        let start = if index == 0 {
+            0.0
+        } else {
+            self.bounds[index - 1]
+        };
+        let end = self.bounds[index];
+        let count = self.counts[index] as f64;
+        start + (end - start) * ((rank - seen as f64) / count)
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::vec(&self.bounds) + HeapSize::vec(&self.counts)
+    }
+
+    /// Number of bounds, the bounds and the counts including the `+Inf` bucket.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + self.bounds.len() * 16 + 8);
+        out.extend_from_slice(&(self.bounds.len() as u32).to_le_bytes());
+        for bound in self.bounds.iter() {
+            out.extend_from_slice(&bound.to_le_bytes());
+        }
+        for count in self.counts.iter() {
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let num_bounds = reader.u32()?;
+        let bounds = (0..num_bounds)
+            .map(|_| reader.f64())
+            .collect::<Result<Vec<_>, _>>()?;
+        let sorted = bounds.windows(2).all(|pair| pair[0] < pair[1]);
+        if !sorted || bounds.iter().any(|bound| !bound.is_finite()) {
+            return Err(DecodeError::Invalid("bucket boundaries"));
+        }
+        let mut histogram = ClassicHistogram::new(bounds);
+        for count in histogram.counts.iter_mut() {
+            *count = reader.u64()?;
+        }
+        histogram.count = histogram.counts.iter().sum();
+        reader.finish()?;
+        Ok(histogram)
+    }
+
+    /// Like `serialize`, but the bounds are delta encoded and the counts are varints.
+    pub fn serialize_varint(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_sorted_f64s(&mut out, &self.bounds);
+        for count in self.counts.iter() {
+            put_varint(&mut out, *count);
+        }
+        out
+    }
+
+    pub fn deserialize_varint(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let bounds = reader.sorted_f64s()?;
+        if bounds.iter().any(|bound| !bound.is_finite()) {
+            return Err(DecodeError::Invalid("bucket boundaries"));
+        }
+        let num_bounds = bounds.len();
+        let mut histogram = ClassicHistogram::new(bounds);
+        if histogram.bounds.len() != num_bounds {
+            return Err(DecodeError::Invalid("bucket boundaries"));
+        }
+        for count in histogram.counts.iter_mut() {
+            *count = reader.varint()?;
+        }
+        histogram.count = histogram.counts.iter().sum();
+        reader.finish()?;
+        Ok(histogram)
+    }
+}
@@ -0,0 +1,538 @@
+//! The `DDSketch` protobuf message of DataDog's sketches-java, sketches-go and sketches-py, used to
+//! exchange sketches with them:
+//!
+//! ```text
+//! message DDSketch {
+//!   IndexMapping mapping = 1;
+//!   Store positiveValues = 2;
+//!   Store negativeValues = 3;
+//!   double zeroCount = 4;
+//! }
+//!
+//! message IndexMapping {
+//!   double gamma = 1;
+//!   double indexOffset = 2;
+//!   enum Interpolation { NONE = 0; LINEAR = 1; QUADRATIC = 2; CUBIC = 3; }
+//!   Interpolation interpolation = 3;
+//! }
+//!
+//! message Store {
+//!   map<sint32, double> binCounts = 1;
+//!   repeated double contiguousBinCounts = 2 [packed = true];
+//!   sint32 contiguousBinIndexOffset = 3;
+//! }
+//! ```
+//!
+//! Stores are written contiguous, as the dense stores of the reference implementations do. Scalars
+//! with the default value are omitted like in proto3. The decoder accepts both store layouts and
+//! skips unknown fields.
+
+use crate::encoding::{put_varint, put_zigzag, DecodeError, Reader};
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    None,
+    Linear,
+    Quadratic,
+    Cubic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexMapping {
+    pub gamma: f64,
+    pub index_offset: f64,
+    pub interpolation: Interpolation,
+}
+
+/// Bin counts of consecutive indices, starting at `index_offset`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Store {
+    pub index_offset: i32,
+    pub counts: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DDSketch {
+    pub mapping: IndexMapping,
+    pub positive: Store,
+    pub negative: Store,
+    pub zero_count: f64,
+}
+
+// Coefficients of the cubic approximation of log2 on the significand.
+const A: f64 = 6.0 / 35.0;
+const B: f64 = -3.0 / 5.0;
+const C: f64 = 10.0 / 7.0;
+
+/// Index computation of the logarithmic and the cubically interpolated mapping of the reference
+/// implementations, to put values recovered from a sketch into the bins of the wire format.
+#[derive(Debug, Clone, Copy)]
+pub struct LogLikeMapping {
+    mapping: IndexMapping,
+    multiplier: f64,
+    relative_accuracy: f64,
+}
+
+impl LogLikeMapping {
+    /// `Interpolation::None` is `LogarithmicMapping`, `Interpolation::Cubic` is
+    /// `CubicallyInterpolatedMapping`.
+    pub fn new(relative_accuracy: f64, interpolation: Interpolation) -> Self {
+        let exact_gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        let gamma = exact_gamma.powf(1.0 / Self::correcting_factor(interpolation));
+        Self::from_proto(&IndexMapping {
+            gamma,
+            index_offset: 0.0,
+            interpolation,
+        })
+        .unwrap()
+    }
+
+    pub fn from_proto(mapping: &IndexMapping) -> Result<Self, DecodeError> {
+        if mapping.gamma.is_nan() || mapping.gamma <= 1.0 || !mapping.index_offset.is_finite() {
+            return Err(DecodeError::Invalid("index mapping"));
+        }
+        if !matches!(
+            mapping.interpolation,
+            Interpolation::None | Interpolation::Cubic
+        ) {
+            return Err(DecodeError::Invalid("interpolation"));
+        }
+        let base: f64 = match mapping.interpolation {
+            Interpolation::Cubic => 2.0,
+            _ => std::f64::consts::E,
+        };
+        let exact_gamma = mapping
+            .gamma
+            .powf(Self::correcting_factor(mapping.interpolation));
+        Ok(Self {
+            mapping: *mapping,
+            multiplier: base.ln() / mapping.gamma.ln(),
+            relative_accuracy: (exact_gamma - 1.0) / (exact_gamma + 1.0),
+        })
+    }
+
+    /// How much wider than exact the bins of the approximated logarithm may get, relative to
+    /// the logarithm in the same base.
+    fn correcting_factor(interpolation: Interpolation) -> f64 {
+        match interpolation {
+            Interpolation::Cubic => 7.0 / (10.0 * std::f64::consts::LN_2),
+            _ => 1.0,
+        }
+    }
+
+    pub fn to_proto(self) -> IndexMapping {
+        self.mapping
+    }
+
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    /// Index of the bin of a positive value. Rounds like the reference implementations, which
+    /// truncate and step down below zero instead of calling floor, so exact negative integers
+    /// land one bin lower than floor would put them.
+    pub fn index(&self, value: f64) -> i32 {
+        let index = self.log(value) * self.multiplier + self.mapping.index_offset;
+        if index >= 0.0 {
+            index as i32
+        } else {
+            index as i32 - 1
+        }
+    }
+
+    /// Representative value of a bin, within the relative accuracy of all values in the bin.
+    pub fn value(&self, index: i32) -> f64 {
+        let lower_bound =
+            self.log_inverse((index as f64 - self.mapping.index_offset) / self.multiplier);
+        lower_bound * (1.0 + self.relative_accuracy)
+    }
+
+    fn log(&self, value: f64) -> f64 {
+        if self.mapping.interpolation != Interpolation::Cubic {
+            return value.ln();
+        }
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64 - 1023;
+        let s = f64::from_bits(bits & 0x000f_ffff_ffff_ffff | 0x3ff0_0000_0000_0000) - 1.0;
+        ((A * s + B) * s + C) * s + exponent as f64
+    }
+
+    fn log_inverse(&self, index: f64) -> f64 {
+        if self.mapping.interpolation != Interpolation::Cubic {
+            return index.exp();
+        }
+        // Cardano's formula for the significand.
+        let exponent = index.floor();
+        let d0 = B * B - 3.0 * A * C;
+        let d1 = 2.0 * B * B * B - 9.0 * A * B * C - 27.0 * A * A * (index - exponent);
+        let p = ((d1 - (d1 * d1 - 4.0 * d0 * d0 * d0).sqrt()) / 2.0).cbrt();
+        let significand_plus_one = -(B + p + d0 / p) / (3.0 * A) + 1.0;
+        significand_plus_one * 2f64.powi(exponent as i32)
+    }
+}
+
+impl Store {
+    pub fn add(&mut self, index: i32, count: f64) {
+        if self.counts.is_empty() {
+            self.index_offset = index;
+        } else if index < self.index_offset {
+            let missing = (self.index_offset - index) as usize;
+            self.counts.splice(0..0, vec![0.0; missing]);
+            self.index_offset = index;
+        }
+        let position = (index - self.index_offset) as usize;
+        if position >= self.counts.len() {
+            self.counts.resize(position + 1, 0.0);
+        }
+        self.counts[position] += count;
+    }
+
+    /// The bins with a count, by ascending index.
+    pub fn bins(&self) -> impl Iterator<Item = (i32, f64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count != 0.0)
+            .map(|(position, count)| (self.index_offset + position as i32, *count))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if !self.counts.is_empty() {
+            put_key(&mut out, 2, LENGTH_DELIMITED);
+            put_varint(&mut out, self.counts.len() as u64 * 8);
+            for count in &self.counts {
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        if self.index_offset != 0 {
+            put_key(&mut out, 3, VARINT);
+            put_zigzag(&mut out, self.index_offset as i64);
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut store = Store::default();
+        let mut contiguous = Vec::new();
+        let mut contiguous_offset = 0;
+        for_each_field(data, |field, value| {
+            match (field, value) {
+                (1, Value::Bytes(entry)) => {
+                    let (mut index, mut count) = (0, 0.0);
+                    for_each_field(entry, |field, value| {
+                        match (field, value) {
+                            (1, Value::Varint(raw)) => index = sint32(raw)?,
+                            (2, Value::Fixed64(bits)) => count = f64::from_bits(bits),
+                            (1 | 2, _) => return Err(DecodeError::Invalid("wire type")),
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    store.add(index, count);
+                }
+                (2, Value::Bytes(packed)) => {
+                    if packed.len() % 8 != 0 {
+                        return Err(DecodeError::Invalid("packed doubles"));
+                    }
+                    let mut reader = Reader::new(packed);
+                    for _ in 0..packed.len() / 8 {
+                        contiguous.push(reader.f64()?);
+                    }
+                }
+                (2, Value::Fixed64(bits)) => contiguous.push(f64::from_bits(bits)),
+                (3, Value::Varint(raw)) => contiguous_offset = sint32(raw)?,
+                (1..=3, _) => return Err(DecodeError::Invalid("wire type")),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        for (position, count) in contiguous.into_iter().enumerate() {
+            if count != 0.0 {
+                store.add(contiguous_offset + position as i32, count);
+            }
+        }
+        Ok(store)
+    }
+}
+
+impl IndexMapping {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_double(&mut out, 1, self.gamma);
+        put_double(&mut out, 2, self.index_offset);
+        if self.interpolation != Interpolation::None {
+            put_key(&mut out, 3, VARINT);
+            put_varint(&mut out, self.interpolation as u64);
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut mapping = IndexMapping {
+            gamma: 0.0,
+            index_offset: 0.0,
+            interpolation: Interpolation::None,
+        };
+        for_each_field(data, |field, value| {
+            match (field, value) {
+                (1, Value::Fixed64(bits)) => mapping.gamma = f64::from_bits(bits),
+                (2, Value::Fixed64(bits)) => mapping.index_offset = f64::from_bits(bits),
+                (3, Value::Varint(raw)) => {
+                    mapping.interpolation = match raw {
+                        0 => Interpolation::None,
+                        1 => Interpolation::Linear,
+                        2 => Interpolation::Quadratic,
+                        3 => Interpolation::Cubic,
+                        _ => return Err(DecodeError::Invalid("interpolation")),
+                    }
+                }
+                (1..=3, _) => return Err(DecodeError::Invalid("wire type")),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(mapping)
+    }
+}
+
+impl DDSketch {
+    pub fn new(mapping: IndexMapping) -> Self {
+        Self {
+            mapping,
+            positive: Store::default(),
+            negative: Store::default(),
+            zero_count: 0.0,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_message(&mut out, 1, &self.mapping.encode());
+        put_message(&mut out, 2, &self.positive.encode());
+        put_message(&mut out, 3, &self.negative.encode());
+        put_double(&mut out, 4, self.zero_count);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut sketch = DDSketch::new(IndexMapping::decode(&[])?);
+        for_each_field(data, |field, value| {
+            match (field, value) {
+                (1, Value::Bytes(bytes)) => sketch.mapping = IndexMapping::decode(bytes)?,
+                (2, Value::Bytes(bytes)) => sketch.positive = Store::decode(bytes)?,
+                (3, Value::Bytes(bytes)) => sketch.negative = Store::decode(bytes)?,
+                (4, Value::Fixed64(bits)) => sketch.zero_count = f64::from_bits(bits),
+                (1..=4, _) => return Err(DecodeError::Invalid("wire type")),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(sketch)
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+/// Calls `f` with the field number and value of every field of a message.
+fn for_each_field<'a>(
+    data: &'a [u8],
+    mut f: impl FnMut(u64, Value<'a>) -> Result<(), DecodeError>,
+) -> Result<(), DecodeError> {
+    let mut reader = Reader::new(data);
+    while !reader.is_empty() {
+        let key = reader.varint()?;
+        let field = key >> 3;
+        if field == 0 {
+            return Err(DecodeError::Invalid("field number"));
+        }
+        let value = match key & 7 {
+            VARINT => Value::Varint(reader.varint()?),
+            FIXED64 => Value::Fixed64(reader.u64()?),
+            LENGTH_DELIMITED => {
+                let len = reader.varint()? as usize;
+                Value::Bytes(reader.bytes(len)?)
+            }
+            FIXED32 => {
+                reader.u32()?;
+                Value::Fixed32
+            }
+            _ => return Err(DecodeError::Invalid("wire type")),
+        };
+        f(field, value)?;
+    }
+    Ok(())
+}
+
+fn put_key(out: &mut Vec<u8>, field: u64, wire_type: u64) {
+    put_varint(out, field << 3 | wire_type);
+}
+
+fn put_double(out: &mut Vec<u8>, field: u64, value: f64) {
+    if value.to_bits() != 0 {
+        put_key(out, field, FIXED64);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_message(out: &mut Vec<u8>, field: u64, message: &[u8]) {
+    put_key(out, field, LENGTH_DELIMITED);
+    put_varint(out, message.len() as u64);
+    out.extend_from_slice(message);
+}
+
+fn sint32(raw: u64) -> Result<i32, DecodeError> {
+    let value = (raw >> 1) as i64 ^ -((raw & 1) as i64);
+    i32::try_from(value).map_err(|_| DecodeError::Invalid("sint32"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Values 1.0, 1.0, 1.05, 1.1, 0.99, -1.0, -1.02 and 0.0 at 1% relative accuracy, index offset
+    // 0. BLOCKED: the bytes were written by a standalone encoder that follows the message
+    // definition above and the index formulas of sketches-java, not by sketches-java
+    // (`DDSketchProtoBinding.toProto(...).toByteArray()`) or sketches-go (`sketch.ToProto()`), so
+    // they carry no generator version. They only pin that encoder. Replace them with the output
+    // of a reference library, and note its version here.
+    pub(crate) const LOGARITHMIC: &[u8] = &[
+        0x0a, 0x09, 0x09, 0xfd, 0x4a, 0x81, 0x5a, 0xbf, 0x52, 0xf0, 0x3f, 0x12, 0x34, 0x12, 0x30,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xf0, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xf0, 0x3f, 0x18, 0x01, 0x1a, 0x0a, 0x12, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x40, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f,
+    ];
+    pub(crate) const CUBIC: &[u8] = &[
+        0x0a, 0x0b, 0x09, 0xc2, 0xf1, 0xe7, 0xe9, 0xed, 0x51, 0xf0, 0x3f, 0x18, 0x03, 0x12, 0x34,
+        0x12, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xf0, 0x3f, 0x18, 0x01, 0x1a, 0x0a, 0x12, 0x08, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x40, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f,
+    ];
+    const LOGARITHMIC_SPARSE: &[u8] = &[
+        0x0a, 0x09, 0x09, 0xfd, 0x4a, 0x81, 0x5a, 0xbf, 0x52, 0xf0, 0x3f, 0x12, 0x34, 0x0a, 0x0b,
+        0x08, 0x01, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x0a, 0x0b, 0x08, 0x00,
+        0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x0a, 0x0b, 0x08, 0x04, 0x11, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x0a, 0x0b, 0x08, 0x08, 0x11, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xf0, 0x3f, 0x1a, 0x0d, 0x0a, 0x0b, 0x08, 0x00, 0x11, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x40, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f,
+    ];
+
+    pub(crate) const VALUES: [f64; 8] = [1.0, 1.0, 1.05, 1.1, 0.99, -1.0, -1.02, 0.0];
+
+    fn sketch_of(mapping: LogLikeMapping) -> DDSketch {
+        let mut sketch = DDSketch::new(mapping.to_proto());
+        for value in VALUES {
+            if value > 0.0 {
+                sketch.positive.add(mapping.index(value), 1.0);
+            } else if value < 0.0 {
+                sketch.negative.add(mapping.index(-value), 1.0);
+            } else {
+                sketch.zero_count += 1.0;
+            }
+        }
+        sketch
+    }
+
+    fn bins(store: &Store) -> Vec<(i32, f64)> {
+        store.bins().collect()
+    }
+
+    #[test]
+    fn gamma_matches_reference() {
+        let logarithmic = LogLikeMapping::new(0.01, Interpolation::None);
+        assert_eq!(logarithmic.to_proto().gamma, 1.01 / 0.99);
+        assert!((logarithmic.relative_accuracy() - 0.01).abs() < 1e-12);
+        let cubic = LogLikeMapping::new(0.01, Interpolation::Cubic);
+        assert!((cubic.to_proto().gamma - 1.0200022828058626).abs() < 1e-15);
+        assert!((cubic.relative_accuracy() - 0.01).abs() < 1e-12);
+    }
+
+    #[test]
+    fn index_rounds_like_reference() {
+        for interpolation in [Interpolation::None, Interpolation::Cubic] {
+            let mapping = LogLikeMapping::new(0.01, interpolation);
+            assert_eq!(mapping.index(1.0), 0);
+            assert_eq!(mapping.index(1.0 + 1e-9), 0);
+            assert_eq!(mapping.index(1.0 - 1e-9), -1);
+            assert_eq!(mapping.index(2.0), mapping.index(2.0 + 1e-12));
+        }
+        // Truncation steps exact negative integers down, floor would keep them.
+        let shifted = LogLikeMapping::from_proto(&IndexMapping {
+            gamma: std::f64::consts::E,
+            index_offset: -3.0,
+            interpolation: Interpolation::None,
+        })
+        .unwrap();
+        assert_eq!(shifted.index(1.0), -4);
+        assert_eq!(shifted.index(std::f64::consts::E.powi(4)), 1);
+    }
+
+    #[test]
+    fn value_within_relative_accuracy() {
+        for interpolation in [Interpolation::None, Interpolation::Cubic] {
+            let mapping = LogLikeMapping::new(0.01, interpolation);
+            let mut value = 1e-6;
+            while value < 1e6 {
+                let representative = mapping.value(mapping.index(value));
+                assert!(
+                    (representative - value).abs() <= 0.01 * value * (1.0 + 1e-9),
+                    "{:?} {} {}",
+                    interpolation,
+                    value,
+                    representative
+                );
+                value *= 1.0137;
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_fixtures() {
+        let logarithmic = sketch_of(LogLikeMapping::new(0.01, Interpolation::None));
+        assert_eq!(logarithmic.encode(), LOGARITHMIC);
+        let cubic = sketch_of(LogLikeMapping::new(0.01, Interpolation::Cubic));
+        assert_eq!(cubic.encode(), CUBIC);
+    }
+
+    #[test]
+    fn decodes_fixtures() {
+        let expected_positive = vec![(-1, 1.0), (0, 2.0), (2, 1.0), (4, 1.0)];
+        let expected_negative = vec![(0, 2.0)];
+        for (bytes, interpolation) in [
+            (LOGARITHMIC, Interpolation::None),
+            (CUBIC, Interpolation::Cubic),
+            (LOGARITHMIC_SPARSE, Interpolation::None),
+        ] {
+            let sketch = DDSketch::decode(bytes).unwrap();
+            let mapping = LogLikeMapping::new(0.01, interpolation).to_proto();
+            assert_eq!(sketch.mapping, mapping);
+            assert_eq!(bins(&sketch.positive), expected_positive);
+            assert_eq!(bins(&sketch.negative), expected_negative);
+            assert_eq!(sketch.zero_count, 1.0);
+        }
+        // A sparse store re-encodes contiguous, as the dense stores of the references do.
+        assert_eq!(
+            DDSketch::decode(LOGARITHMIC_SPARSE).unwrap().encode(),
+            LOGARITHMIC
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        assert!(DDSketch::decode(&LOGARITHMIC[..LOGARITHMIC.len() - 1]).is_err());
+    }
+}
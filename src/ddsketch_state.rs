@@ -0,0 +1,217 @@
+//! The bins of `sketches_ddsketch::DDSketch`, which the crate keeps private. They are read and
+//! written through its serde implementation, by field name via `serde_json::Value`, so fields this
+//! module does not know are kept as they are.
+//!
+//! The crate maps a value to the key `ceil(log_gamma(value))`, the bin `(gamma^(key - 1),
+//! gamma^key]`. The logarithmic mapping of the reference implementations puts a value into
+//! `floor(log_gamma(value)) + index_offset`, the bin `[gamma^(index - index_offset),
+//! gamma^(index - index_offset + 1))`. With `index_offset` 1 key and index are the same, only exact
+//! powers of gamma (in practice 1.0) sit in the bin below the one the reference picks.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ddsketch_proto::{self, IndexMapping, Interpolation};
+use crate::heap_size::HeapSize;
+
+/// Bins are allocated in chunks of this many, like the crate does.
+const CHUNK_SIZE: usize = 128;
+
+#[derive(Deserialize)]
+struct SketchState {
+    config: ConfigState,
+    store: StoreState,
+    negative_store: StoreState,
+    zero_count: u64,
+}
+
+#[derive(Deserialize)]
+struct ConfigState {
+    gamma: f64,
+}
+
+/// `CollapsingLowestDenseStore`, `bins[i]` counts the key `offset + i`.
+#[derive(Serialize, Deserialize)]
+struct StoreState {
+    bins: Vec<u64>,
+    count: u64,
+    min_key: i32,
+    max_key: i32,
+    offset: i32,
+    bin_limit: usize,
+    is_collapsed: bool,
+}
+
+impl StoreState {
+    /// A store of `(key, count)` pairs by ascending key. Keys beyond `bin_limit` below the
+    /// highest key are collapsed into the lowest bin, as the store does when it grows.
+    fn from_bins(bins: &[(i32, u64)], bin_limit: usize) -> Self {
+        let mut store = StoreState {
+            bins: Vec::new(),
+            count: 0,
+            min_key: i32::MAX,
+            max_key: i32::MIN,
+            offset: 0,
+            bin_limit,
+            is_collapsed: false,
+        };
+        let (Some(first), Some(last)) = (bins.first(), bins.last()) else {
+            return store;
+        };
+        store.max_key = last.0;
+        store.min_key = first.0.max(last.0.saturating_sub(bin_limit as i32 - 1));
+        store.is_collapsed = first.0 < store.min_key;
+        store.offset = store.min_key;
+        let len = (store.max_key - store.min_key) as usize + 1;
+        store.bins = vec![0; (len.div_ceil(CHUNK_SIZE) * CHUNK_SIZE).min(bin_limit)];
+        for (key, count) in bins {
+            store.bins[((*key).max(store.min_key) - store.offset) as usize] += count;
+            store.count += count;
+        }
+        store
+    }
+
+    fn bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| (self.offset + i as i32, *count))
+    }
+}
+
+fn state(sketch: &sketches_ddsketch::DDSketch) -> Result<SketchState, serde_json::Error> {
+    serde_json::from_value(serde_json::to_value(sketch)?)
+}
+
+/// The sketch as protobuf message, with the logarithmic mapping at index offset 1.
+pub fn to_proto(
+    sketch: &sketches_ddsketch::DDSketch,
+) -> Result<ddsketch_proto::DDSketch, serde_json::Error> {
+    let state = state(sketch)?;
+    let mut proto = ddsketch_proto::DDSketch::new(IndexMapping {
+        gamma: state.config.gamma,
+        index_offset: 1.0,
+        interpolation: Interpolation::None,
+    });
+    for (key, count) in state.store.bins() {
+        proto.positive.add(key, count as f64);
+    }
+    for (key, count) in state.negative_store.bins() {
+        proto.negative.add(key, count as f64);
+    }
+    proto.zero_count = state.zero_count as f64;
+    Ok(proto)
+}
+
+/// A sketch with the default bin limit from a message with a logarithmic mapping and an integer
+/// index offset. The message has no minimum, maximum and sum, they are taken from the bins.
+/// Counts are rounded to whole values.
+pub fn from_proto(
+    proto: &ddsketch_proto::DDSketch,
+) -> Result<sketches_ddsketch::DDSketch, Box<dyn std::error::Error>> {
+    let mapping = ddsketch_proto::LogLikeMapping::from_proto(&proto.mapping)?;
+    if proto.mapping.interpolation != Interpolation::None
+        || proto.mapping.index_offset.fract() != 0.0
+        || proto.mapping.index_offset.abs() > f64::from(i32::MAX)
+    {
+        return Err("the index mapping does not match the sketch".into());
+    }
+    let defaults = sketches_ddsketch::Config::defaults();
+    let config = sketches_ddsketch::Config::new(
+        mapping.relative_accuracy(),
+        defaults.max_num_bins,
+        defaults.min_possible(),
+    );
+    let shift = 1 - proto.mapping.index_offset as i64;
+    let keys = |store: &ddsketch_proto::Store| -> Result<Vec<(i32, u64)>, &'static str> {
+        store
+            .bins()
+            .map(|(index, count)| {
+                let key = i32::try_from(index as i64 + shift).map_err(|_| "index out of range")?;
+                Ok((key, count.round() as u64))
+            })
+            .filter(|bin| !matches!(bin, Ok((_, 0))))
+            .collect()
+    };
+    let (positive, negative) = (keys(&proto.positive)?, keys(&proto.negative)?);
+    let zero_count = proto.zero_count.round() as u64;
+
+    let sketch = sketches_ddsketch::DDSketch::new(config);
+    let value_of = |sign: f64| move |(key, _): &(i32, u64)| sign * config.value(*key);
+    let zero = (zero_count > 0).then_some(0.0);
+    let min = negative.last().map(value_of(-1.0)).or(zero);
+    let max = positive.last().map(value_of(1.0)).or(zero);
+    let (Some(min), Some(max)) = (
+        min.or(positive.first().map(value_of(1.0))),
+        max.or(negative.first().map(value_of(-1.0))),
+    ) else {
+        return Ok(sketch);
+    };
+    let total = |sign: f64, bins: &[(i32, u64)]| -> f64 {
+        bins.iter()
+            .map(|bin| value_of(sign)(bin) * bin.1 as f64)
+            .sum()
+    };
+    let sum = total(1.0, &positive) + total(-1.0, &negative);
+
+    let bin_limit = defaults.max_num_bins as usize;
+    let mut value = serde_json::to_value(&sketch)?;
+    patch(
+        &mut value["store"],
+        serde_json::to_value(StoreState::from_bins(&positive, bin_limit))?,
+    );
+    patch(
+        &mut value["negative_store"],
+        serde_json::to_value(StoreState::from_bins(&negative, bin_limit))?,
+    );
+    value["min"] = min.into();
+    value["max"] = max.into();
+    value["sum"] = sum.into();
+    value["zero_count"] = zero_count.into();
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Overwrites the fields of the object `target` with the fields of `fields`.
+fn patch(target: &mut Value, fields: Value) {
+    if let (Value::Object(target), Value::Object(fields)) = (target, fields) {
+        target.extend(fields);
+    }
+}
+
+/// The bins of both stores, their capacity is not accessible.
+pub fn heap_size(sketch: &sketches_ddsketch::DDSketch) -> Result<HeapSize, serde_json::Error> {
+    let state = state(sketch)?;
+    Ok(HeapSize {
+        live: (state.store.bins.len() + state.negative_store.bins.len())
+            * std::mem::size_of::<u64>(),
+        overhead: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_lowest_bins() {
+        let mapping = ddsketch_proto::LogLikeMapping::new(0.01, Interpolation::None);
+        let mut proto = ddsketch_proto::DDSketch::new(IndexMapping {
+            index_offset: 1.0,
+            ..mapping.to_proto()
+        });
+        proto.positive.add(-3000, 2.0);
+        proto.positive.add(0, 1.0);
+        proto.positive.add(5000, 1.0);
+        let sketch = from_proto(&proto).unwrap();
+        assert_eq!(sketch.count(), 4);
+        let store = state(&sketch).unwrap().store;
+        assert!(store.is_collapsed);
+        assert_eq!(store.min_key, 5000 - 2047);
+        assert_eq!(
+            store.bins().collect::<Vec<_>>(),
+            [(5000 - 2047, 3), (5000, 1)]
+        );
+        assert_eq!(to_proto(&sketch).unwrap().positive.bins().count(), 2);
+    }
+}
@@ -0,0 +1,358 @@
+//! Float support for `hdrhistogram`, which only records `u64` values.
+//!
+//! - `ScaledHistogram`: multiplies values by a fixed factor before recording and divides on
+//!   query. The factor is either configured or detected from the first values.
+//! - `DoubleHistogram`: modeled after `DoubleHistogram` of the Java implementation. Values are
+//!   recorded as `value / 2^exponent`, the exponent is chosen with the first value and shifted
+//!   when later values don't fit the covered dynamic range anymore ("auto-ranging").
+//!
+//! Negative values are not supported by HdrHistogram and are recorded as 0.
+use std::io::Read;
+
+use hdrhistogram::serialization::{
+    DeserializeError, Deserializer, Serializer, V2SerializeError, V2Serializer,
+};
+use hdrhistogram::{AdditionError, Histogram};
+
+use crate::heap_size::HeapSize;
+
+/// Number of values buffered to detect the scale.
+pub const AUTO_DETECT_SAMPLES: usize = 1000;
+
+/// Largest value an auto-resizing `Histogram` can track.
+const MAX_TRACKABLE: u64 = (i64::MAX / 2) as u64;
+
+/// Largest scaled value of the detection sample, leaves headroom for larger values later on.
+const MAX_SCALED: f64 = (1u64 << 52) as f64;
+
+/// `x * 2^exp`, without overflowing the intermediate power of two for large exponents.
+fn ldexp(mut x: f64, mut exp: i32) -> f64 {
+    while exp > 1000 {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    x * 2f64.powi(exp)
+}
+
+/// Re-records all values of `histogram` with `map` applied into an empty copy of it.
+fn remap(histogram: &Histogram<u64>, map: impl Fn(u64) -> u64) -> Histogram<u64> {
+    let mut remapped = Histogram::new_from(histogram);
+    for value in histogram.iter_recorded() {
+        let mid = map(histogram.median_equivalent(value.value_iterated_to()));
+        if remapped.record_n(mid, value.count_at_value()).is_err() {
+            remapped.saturating_record_n(mid, value.count_at_value());
+        }
+    }
+    remapped
+}
+
+fn read_array<const N: usize>(reader: &mut &[u8]) -> Result<[u8; N], DeserializeError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Chooses a power of ten, so that the smallest positive value is recorded with `sigfig`
+/// significant digits, limited by the largest value.
+fn detect_scale(values: &[f64], sigfig: u8) -> f64 {
+    let positive = values
+        .iter()
+        .filter(|value| **value > 0.0 && value.is_finite());
+    let min = positive
+        .clone()
+        .fold(f64::INFINITY, |acc, value| acc.min(*value));
+    let max = positive.fold(0f64, |acc, value| acc.max(*value));
+    if max == 0.0 {
+        return 1.0;
+    }
+    let scale = 10f64.powi(sigfig as i32 - min.log10().floor() as i32);
+    let mut limit = 10f64.powi((MAX_SCALED / max).log10().floor() as i32);
+    if limit == 0.0 {
+        limit = MAX_SCALED / max;
+    }
+    scale.min(limit)
+}
+
+#[derive(Debug, Clone)]
+pub struct ScaledHistogram {
+    histogram: Histogram<u64>,
+    sigfig: u8,
+    /// `None` while the scale is detected.
+    scale: Option<f64>,
+    pending: Vec<f64>,
+}
+
+impl ScaledHistogram {
+    pub fn with_scale(sigfig: u8, scale: f64) -> Self {
+        assert!(scale > 0.0 && scale.is_finite(), "scale must be positive");
+        Self {
+            histogram: Histogram::new(sigfig).unwrap(),
+            sigfig,
+            scale: Some(scale),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Detects the scale from the first `AUTO_DETECT_SAMPLES` values.
+    pub fn auto(sigfig: u8) -> Self {
+        Self {
+            histogram: Histogram::new(sigfig).unwrap(),
+            sigfig,
+            scale: None,
+            pending: Vec::with_capacity(AUTO_DETECT_SAMPLES),
+        }
+    }
+
+    pub fn record(&mut self, value: f64) {
+        match self.scale {
+            Some(scale) => self.record_scaled(value, scale),
+            None => {
+                self.pending.push(value);
+                if self.pending.len() == AUTO_DETECT_SAMPLES {
+                    self.finish_detection();
+                }
+            }
+        }
+    }
+
+    fn record_scaled(&mut self, value: f64, scale: f64) {
+        // `as` saturates, negative values end up as 0.
+        let scaled = ((value * scale).round() as u64).min(MAX_TRACKABLE);
+        self.histogram.record(scaled).unwrap();
+    }
+
+    /// Fixes the scale with the values seen so far.
+    fn finish_detection(&mut self) -> f64 {
+        if let Some(scale) = self.scale {
+            return scale;
+        }
+        let scale = detect_scale(&self.pending, self.sigfig);
+        self.scale = Some(scale);
+        for value in std::mem::take(&mut self.pending) {
+            self.record_scaled(value, scale);
+        }
+        scale
+    }
+
+    /// The other histograms are converted to the finest scale of all.
+    pub fn merge(&mut self, others: Vec<ScaledHistogram>) -> Result<(), AdditionError> {
+        let mut others: Vec<ScaledHistogram> = others;
+        let scale = others
+            .iter_mut()
+            .map(|other| other.finish_detection())
+            .fold(self.finish_detection(), f64::max);
+        self.rescale(scale);
+        for mut other in others {
+            other.rescale(scale);
+            self.histogram.add(&other.histogram)?;
+        }
+        Ok(())
+    }
+
+    fn rescale(&mut self, scale: f64) {
+        let current = self.finish_detection();
+        if current != scale {
+            let factor = scale / current;
+            self.histogram = remap(&self.histogram, |value| {
+                (value as f64 * factor).round() as u64
+            });
+            self.scale = Some(scale);
+        }
+    }
+
+    pub fn quantile(&mut self, q: f64) -> f64 {
+        let scale = self.finish_detection();
+        self.histogram.value_at_quantile(q) as f64 / scale
+    }
+
+    /// Includes the values buffered for the scale detection.
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::hdr_histogram(&self.histogram) + HeapSize::vec(&self.pending)
+    }
+
+    /// The scale as `f64`, followed by the histogram in the V2 format. Pending values of the scale
+    /// detection are recorded first.
+    pub fn serialize(&mut self) -> Result<Vec<u8>, V2SerializeError> {
+        let mut out = self.finish_detection().to_le_bytes().to_vec();
+        V2Serializer::new().serialize(&self.histogram, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DeserializeError> {
+        let mut reader = data;
+        let scale = f64::from_le_bytes(read_array(&mut reader)?);
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(DeserializeError::InvalidParameters);
+        }
+        let mut histogram: Histogram<u64> = Deserializer::new().deserialize(&mut reader)?;
+        histogram.auto(true);
+        Ok(Self {
+            sigfig: histogram.sigfig(),
+            histogram,
+            scale: Some(scale),
+            pending: Vec::new(),
+        })
+    }
+}
+
+/// Bits needed to record integers with the full precision of `sigfig`.
+fn precision_bits(sigfig: u8) -> u32 {
+    (2.0 * 10f64.powi(sigfig as i32)).log2().ceil() as u32 - 1
+}
+
+#[derive(Debug, Clone)]
+pub struct DoubleHistogram {
+    histogram: Histogram<u64>,
+    /// Recorded integers are `value / 2^exponent`. `None` until the first positive value.
+    exponent: Option<i32>,
+    dynamic_range_bits: u32,
+    /// Integers from `2^precision_bits` on are recorded with the full precision of `sigfig`.
+    precision_bits: u32,
+    highest: u64,
+}
+
+impl DoubleHistogram {
+    /// Covers a ratio of `2^dynamic_range_bits` between the smallest and the largest value with
+    /// full precision. Values outside the range shift the range, the lowest values then lose
+    /// precision.
+    pub fn new(dynamic_range_bits: u32, sigfig: u8) -> Self {
+        let precision_bits = precision_bits(sigfig);
+        assert!(
+            precision_bits + dynamic_range_bits <= 62,
+            "dynamic range too large for sigfig"
+        );
+        let highest = 1u64 << (precision_bits + dynamic_range_bits);
+        Self {
+            histogram: Histogram::new_with_max(highest, sigfig).unwrap(),
+            exponent: None,
+            dynamic_range_bits,
+            precision_bits,
+            highest,
+        }
+    }
+
+    pub fn exponent(&self) -> i32 {
+        self.exponent.unwrap_or(0)
+    }
+
+    pub fn record(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        if value <= 0.0 {
+            self.histogram.saturating_record(0);
+            return;
+        }
+        let exponent = *self.exponent.get_or_insert_with(|| {
+            // Start in the middle of the dynamic range, to leave room in both directions.
+            value.log2().floor() as i32 - (self.precision_bits + self.dynamic_range_bits / 2) as i32
+        });
+        let mut scaled = ldexp(value, -exponent);
+        if scaled > self.highest as f64 {
+            // Computed in log space, `scaled` may be infinite for values close to `f64::MAX`.
+            let shift =
+                (value.log2() - exponent as f64 - (self.highest as f64).log2()).ceil() as i32;
+            self.shift(shift);
+            scaled = ldexp(value, -(exponent + shift));
+        } else if scaled < (1u64 << self.precision_bits) as f64 {
+            let needed = ((1u64 << self.precision_bits) as f64 / scaled)
+                .log2()
+                .ceil() as i32;
+            let headroom = (self.highest / self.histogram.max().max(1)).ilog2() as i32;
+            let shift = needed.min(headroom);
+            if shift > 0 {
+                self.shift(-shift);
+                scaled = ldexp(value, -(exponent - shift));
+            }
+        }
+        self.histogram.saturating_record(scaled as u64);
+    }
+
+    /// Changes the exponent by `shift`, positive values make the histogram coarser.
+    fn shift(&mut self, shift: i32) {
+        if shift == 0 {
+            return;
+        }
+        if !self.histogram.is_empty() {
+            self.histogram = remap(&self.histogram, |value| {
+                if shift > 0 {
+                    value.checked_shr(shift as u32).unwrap_or(0)
+                } else {
+                    value << -shift
+                }
+            });
+        }
+        if let Some(exponent) = self.exponent.as_mut() {
+            *exponent += shift;
+        }
+    }
+
+    /// All histograms are shifted to the coarsest exponent before they are added.
+    pub fn merge(&mut self, others: Vec<DoubleHistogram>) -> Result<(), AdditionError> {
+        let exponent = others
+            .iter()
+            .chain(std::iter::once(&*self))
+            .filter_map(|histogram| histogram.exponent)
+            .max();
+        let Some(exponent) = exponent else {
+            for other in others {
+                self.histogram.add(&other.histogram)?;
+            }
+            return Ok(());
+        };
+        self.align(exponent);
+        for mut other in others {
+            other.align(exponent);
+            self.histogram.add(&other.histogram)?;
+        }
+        Ok(())
+    }
+
+    fn align(&mut self, exponent: i32) {
+        match self.exponent {
+            Some(current) => self.shift(exponent - current),
+            // Only zeros have been recorded so far.
+            None => self.exponent = Some(exponent),
+        }
+    }
+
+    pub fn quantile(&self, q: f64) -> f64 {
+        ldexp(self.histogram.value_at_quantile(q) as f64, self.exponent())
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::hdr_histogram(&self.histogram)
+    }
+
+    /// The dynamic range bits as `u32`, the exponent as `i32` preceded by a presence byte and the
+    /// histogram in the V2 format.
+    pub fn serialize(&self) -> Result<Vec<u8>, V2SerializeError> {
+        let mut out = self.dynamic_range_bits.to_le_bytes().to_vec();
+        out.push(self.exponent.is_some() as u8);
+        out.extend_from_slice(&self.exponent().to_le_bytes());
+        V2Serializer::new().serialize(&self.histogram, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DeserializeError> {
+        let mut reader = data;
+        let dynamic_range_bits = u32::from_le_bytes(read_array(&mut reader)?);
+        let has_exponent = read_array::<1>(&mut reader)?[0] != 0;
+        let exponent = i32::from_le_bytes(read_array(&mut reader)?);
+        let histogram: Histogram<u64> = Deserializer::new().deserialize(&mut reader)?;
+        if precision_bits(histogram.sigfig()) + dynamic_range_bits > 62 {
+            return Err(DeserializeError::InvalidParameters);
+        }
+        let mut double = DoubleHistogram::new(dynamic_range_bits, histogram.sigfig());
+        if histogram.high() > double.highest {
+            return Err(DeserializeError::InvalidParameters);
+        }
+        double.histogram = histogram;
+        double.exponent = has_exponent.then_some(exponent);
+        Ok(double)
+    }
+}
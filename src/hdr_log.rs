@@ -0,0 +1,69 @@
+//! HdrHistogram interval logs, as written by the Java `HistogramLogWriter`: one line per interval
+//! with its start, duration, max and the base64 encoded, deflate compressed V2 histogram.
+//!
+//! Recorded logs can be replayed as an input source, see `replay`.
+
+use std::time::Duration;
+
+use hdrhistogram::{
+    serialization::{interval_log, Deserializer, V2DeflateSerializer},
+    Histogram,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// A histogram of an interval log with its start, relative to the start or base time of the log.
+pub struct Interval {
+    pub start: Duration,
+    pub duration: Duration,
+    pub histogram: Histogram<u64>,
+}
+
+/// Writes the intervals with compressed histograms, like the Java implementation.
+pub fn write(intervals: &[Interval]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut out = Vec::new();
+    let mut serializer = V2DeflateSerializer::new();
+    let mut writer = interval_log::IntervalLogWriterBuilder::new()
+        .with_base_time(std::time::UNIX_EPOCH)
+        .begin_log_with(&mut out, &mut serializer)?;
+    for interval in intervals {
+        writer.write_histogram(&interval.histogram, interval.start, interval.duration, None)?;
+    }
+    Ok(out)
+}
+
+/// Reads all intervals of a log, tags and the start and base time are ignored.
+pub fn read(log: &[u8]) -> Result<Vec<Interval>, Box<dyn std::error::Error>> {
+    let mut intervals = Vec::new();
+    for entry in interval_log::IntervalLogIterator::new(log) {
+        let entry = entry.map_err(|err| format!("invalid interval log: {:?}", err))?;
+        if let interval_log::LogEntry::Interval(interval) = entry {
+            let bytes = base64::decode(interval.encoded_histogram())?;
+            let mut histogram: Histogram<u64> = Deserializer::new().deserialize(&mut &bytes[..])?;
+            histogram.auto(true);
+            intervals.push(Interval {
+                start: interval.start_timestamp(),
+                duration: interval.duration(),
+                histogram,
+            });
+        }
+    }
+    Ok(intervals)
+}
+
+/// The recorded values of all intervals in the order of the intervals. The order within an
+/// interval is not recorded, the values of an interval are shuffled.
+pub fn replay(intervals: &[Interval]) -> Vec<f64> {
+    let mut rng = StdRng::from_seed([1u8; 32]);
+    let mut values = Vec::new();
+    for interval in intervals {
+        let start = values.len();
+        for value in interval.histogram.iter_recorded() {
+            let recorded = interval
+                .histogram
+                .median_equivalent(value.value_iterated_to()) as f64;
+            values.resize(values.len() + value.count_at_value() as usize, recorded);
+        }
+        values[start..].shuffle(&mut rng);
+    }
+    values
+}
@@ -0,0 +1,91 @@
+//! Heap memory of the collections used by the aggregates, computed from their length and
+//! capacity. The layouts of `HashMap` and `BTreeMap` follow the std implementation, for `BTreeMap`
+//! the node occupancy is estimated.
+
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::mem::size_of;
+
+/// `live` bytes hold elements, `overhead` bytes are reserved but unused capacity or bookkeeping
+/// of the collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapSize {
+    pub live: usize,
+    pub overhead: usize,
+}
+
+impl HeapSize {
+    pub fn total(&self) -> usize {
+        self.live + self.overhead
+    }
+
+    pub fn vec<T>(vec: &Vec<T>) -> Self {
+        Self::with_capacity::<T>(vec.len(), vec.capacity())
+    }
+
+    pub fn binary_heap<T>(heap: &BinaryHeap<T>) -> Self {
+        Self::with_capacity::<T>(heap.len(), heap.capacity())
+    }
+
+    fn with_capacity<T>(len: usize, capacity: usize) -> Self {
+        Self {
+            live: len * size_of::<T>(),
+            overhead: (capacity - len) * size_of::<T>(),
+        }
+    }
+
+    /// The counts array of the histogram, its capacity is not accessible.
+    pub fn hdr_histogram(histogram: &hdrhistogram::Histogram<u64>) -> Self {
+        Self {
+            live: histogram.distinct_values() * size_of::<u64>(),
+            overhead: 0,
+        }
+    }
+
+    /// A swiss table with one control byte per bucket and a trailing group of 16 control bytes.
+    pub fn hash_map<K, V>(map: &HashMap<K, V>) -> Self {
+        let buckets = match map.capacity() {
+            0 => return Self::default(),
+            capacity if capacity < 7 => capacity + 1,
+            capacity => capacity / 7 * 8,
+        };
+        let allocated = buckets * (size_of::<(K, V)>() + 1) + 16;
+        let live = map.len() * size_of::<(K, V)>();
+        Self {
+            live,
+            overhead: allocated - live,
+        }
+    }
+
+    /// Leaf nodes hold up to 11 entries, they are assumed to be two thirds full. Internal nodes
+    /// are ignored.
+    pub fn btree_map<K, V>(map: &BTreeMap<K, V>) -> Self {
+        const CAPACITY: usize = 11;
+        if map.is_empty() {
+            return Self::default();
+        }
+        let nodes = (map.len() * 3).div_ceil(CAPACITY * 2);
+        let node_size = 16 + CAPACITY * (size_of::<K>() + size_of::<V>());
+        let live = map.len() * (size_of::<K>() + size_of::<V>());
+        Self {
+            live,
+            overhead: (nodes * node_size).saturating_sub(live),
+        }
+    }
+}
+
+impl std::ops::Add for HeapSize {
+    type Output = HeapSize;
+
+    fn add(self, other: HeapSize) -> HeapSize {
+        HeapSize {
+            live: self.live + other.live,
+            overhead: self.overhead + other.overhead,
+        }
+    }
+}
+
+impl std::fmt::Display for HeapSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}k (+{}k)", self.live / 1024, self.overhead / 1024)
+    }
+}
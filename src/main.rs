@@ -1,7 +1,7 @@
 use std::io::{BufRead, BufReader};
 
 use hdrhistogram::{
-    serialization::{Serializer, V2Serializer},
+    serialization::{Deserializer, Serializer, V2DeflateSerializer, V2Serializer},
     Histogram,
 };
 use itertools::Itertools;
@@ -9,24 +9,69 @@ use prettytable::{
     format::{FormatBuilder, LinePosition, LineSeparator},
     row, Cell, Row, Table,
 };
-use rand::{rngs::StdRng, SeedableRng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rand_distr::Distribution;
+use serde::{Deserialize, Serialize};
 use zw_fast_quantile::UnboundEpsilonSummary;
 
+use heap_size::HeapSize;
 use peakmem_alloc::PeakAlloc;
+use tracking_alloc::TrackingAlloc;
 
-// INSTRUMENTED_SYSTEM is an instrumented instance of the system allocator
+mod chart;
+mod circllhist;
+mod classic_histogram;
+mod ddsketch_proto;
+mod ddsketch_state;
+mod encoding;
+mod hdr_float;
+mod hdr_log;
+mod heap_size;
+mod native_histogram;
+mod qdigest;
+mod reservoir;
+mod scenarios;
+mod tracking_alloc;
+
+// INSTRUMENTED_SYSTEM is an instrumented instance of the system allocator, the wrapper adds the
+// currently allocated bytes
 #[global_allocator]
-static GLOBAL: &PeakAlloc<std::alloc::System> = &peakmem_alloc::INSTRUMENTED_SYSTEM;
+static GLOBAL: TrackingAlloc<PeakAlloc<std::alloc::System>> =
+    TrackingAlloc::new(&peakmem_alloc::INSTRUMENTED_SYSTEM);
 
 fn main() {
     test_counts();
     //test_gk_and_cksm_params();
     //test_digest_params();
     //test_sketch_params();
+    //test_native_histogram_params();
+    //test_classic_histogram_buckets();
+    //test_integer_data();
+    //test_reservoir_sizes();
+    //test_hdr_float_params();
+    //test_signed_values();
+    //test_invalid_values();
+    //test_serialization_round_trip();
+    //test_encodings();
+    //test_hdr_interval_log();
+    //scenarios::merge_from_bytes::test_merge_from_bytes();
+    //scenarios::memory_curves::test_memory_curves();
+    //scenarios::allocation_counts::test_allocation_counts();
+    //scenarios::many_sketches::test_many_sketches();
+    //scenarios::merge_topologies::test_merge_topologies();
+    //scenarios::serialize_merge_cycles::test_serialize_merge_cycles();
+    //scenarios::mixed_config_merge::test_mixed_config_merge();
+    //test_windowed_quantiles();
+    //test_decayed_quantiles();
 }
 
-fn get_distributions() -> Vec<(&'static str, Box<dyn FnMut(usize) -> f64>)> {
+/// Produces the value with the given index.
+type Sampler = Box<dyn FnMut(usize) -> f64>;
+
+/// A named constructor of a bucket layout.
+type ClassicLayout = (&'static str, Box<dyn Fn() -> ClassicHistogram>);
+
+fn get_distributions() -> Vec<(&'static str, Sampler)> {
     let dn = rand_distr::Normal::new(0.5f64, 0.2f64).unwrap();
     let dp = rand_distr::Pareto::new(5f64, 10f64).unwrap();
     //let exp = rand_distr::Exp::new(0.6).unwrap();
@@ -46,7 +91,7 @@ fn get_distributions() -> Vec<(&'static str, Box<dyn FnMut(usize) -> f64>)> {
     let mut rng3 = StdRng::from_seed([1u8; 32]);
     let mut rng4 = StdRng::from_seed([1u8; 32]);
     //let mut rng5 = StdRng::from_seed([1u8; 32]);
-    let distributions: Vec<(&str, Box<dyn FnMut(usize) -> f64>)> = vec![
+    let mut distributions: Vec<(&str, Sampler)> = vec![
         (
             "Normal Distribution",
             Box::new(move |_| dn.sample(&mut rng1)),
@@ -69,13 +114,78 @@ fn get_distributions() -> Vec<(&'static str, Box<dyn FnMut(usize) -> f64>)> {
         ),
         //("Exp Distribution", Box::new(move |_| exp.sample(&mut rng5))),
     ];
+    // A recorded HdrHistogram interval log, e.g. of a Java service, see `hdr_log::replay`.
+    if let Ok(path) = std::env::var("HDR_LOG") {
+        let log = std::fs::read(path).unwrap();
+        let values = hdr_log::replay(&hdr_log::read(&log).unwrap());
+        distributions.push((
+            "HdrHistogram Interval Log",
+            Box::new(move |index| values[index % values.len()]),
+        ));
+    }
+    distributions
+}
+
+/// Integer valued data, e.g. queue lengths or byte counts.
+fn get_integer_distributions() -> Vec<(&'static str, Sampler)> {
+    let poisson = rand_distr::Poisson::new(20f64).unwrap();
+    let zipf = rand_distr::Zipf::new(10_000, 1.1f64).unwrap();
+    let lg_norm = rand_distr::LogNormal::new(8f64, 1.5f64).unwrap();
+
+    let mut rng1 = StdRng::from_seed([1u8; 32]);
+    let mut rng2 = StdRng::from_seed([1u8; 32]);
+    let mut rng3 = StdRng::from_seed([1u8; 32]);
+    let distributions: Vec<(&str, Sampler)> = vec![
+        (
+            "Poisson Queue Length",
+            Box::new(move |_| poisson.sample(&mut rng1)),
+        ),
+        ("Zipf Ranks", Box::new(move |_| zipf.sample(&mut rng2))),
+        (
+            "LogNorm Byte Counts",
+            Box::new(move |_| lg_norm.sample(&mut rng3).round()),
+        ),
+    ];
+    distributions
+}
+
+/// Negative and mixed-sign data, e.g. temperatures, deltas or profit and loss.
+fn get_signed_distributions() -> Vec<(&'static str, Sampler)> {
+    let dn = rand_distr::Normal::new(0f64, 1f64).unwrap();
+    let lg_norm = rand_distr::LogNormal::new(2.996f64, 0.979f64).unwrap();
+    let dp = rand_distr::Pareto::new(5f64, 10f64).unwrap();
+
+    let mut rng1 = StdRng::from_seed([1u8; 32]);
+    let mut rng2 = StdRng::from_seed([1u8; 32]);
+    let mut rng3 = StdRng::from_seed([1u8; 32]);
+    let distributions: Vec<(&str, Sampler)> = vec![
+        (
+            "Normal Distribution Centered",
+            Box::new(move |_| dn.sample(&mut rng1)),
+        ),
+        (
+            "Negated LogNorm Distribution",
+            Box::new(move |_| -lg_norm.sample(&mut rng2)),
+        ),
+        (
+            "Mixed-Sign Pareto Distribution",
+            Box::new(move |_| {
+                let value = dp.sample(&mut rng3);
+                if rng3.gen_bool(0.3) {
+                    -value
+                } else {
+                    value
+                }
+            }),
+        ),
+    ];
     distributions
 }
 
 #[allow(dead_code)]
 fn test_counts() {
     // If there are multiple counts, the Algorithm has to support `merge`.
-    let counts = vec![
+    let counts = [
         vec![1_000],
         //vec![1_000_000],
         vec![5_000_000],
@@ -83,71 +193,93 @@ fn test_counts() {
         (0..1000).map(|_| 1000).collect::<Vec<_>>(),
         //(0..10000).map(|_| 100).collect::<Vec<_>>(),
     ];
-    //let ckms_error = 0.0001;
-    //let gk_error = 0.001;
-    //let zw_error = 0.001;
+    let ckms_error = 0.0001;
+    let gk_error = 0.001;
+    let zw_error = 0.001;
     let hdr_sigfig = 2;
+    let hdr_range_bits = 40;
     let tdigest_batch = 500;
     let tdigest_max_size = 300;
     let dd2_err = 0.01;
+    let prom_schema = 3;
+    let reservoir_size = 10_000;
 
     let mut distributions = get_distributions();
 
     for (distr, distribution) in &mut distributions {
-        for count_group in counts.iter().cloned() {
+        for count_group in counts.iter() {
             let all = || AllValues::new();
-            //let mut aq = QuantilesCKMS::new(ckms_error);
-            //let mut ag = QuantilesGK::new(gk_error);
+            let aq = || QuantilesCKMS::new(ckms_error);
+            let ag = || QuantilesGK::new(gk_error);
             let td = || TDigest::new(tdigest_batch, tdigest_max_size);
-            //let mut zw = ZWQuantile::new(zw_error);
-            let hdr = || HDRHistogram::new(hdr_sigfig);
+            let zw = || ZWQuantile::new(zw_error);
+            // The normal distribution has a few negative values, which HDRHistogram clamps.
+            let hdr = || Mirrored::new(|| HDRHistogram::new(hdr_sigfig));
+            let hdr_scaled = || Mirrored::new(|| HDRScaledHistogram::auto(hdr_sigfig));
+            let hdr_double =
+                || Mirrored::new(|| HDRDoubleHistogram::new(hdr_range_bits, hdr_sigfig));
             let dd = || DDSketch::new();
             let dd2 = || DDSketch2::unbounded(dd2_err);
             let quanto = || Quantogram::new();
+            let circ = || Circllhist::new();
+            let reservoir = || Reservoir::new(reservoir_size);
+            let prom =
+                || PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD);
             //let dd3 = || DDSketch2::logarithmic_low(dd2_err);
             //let dd4 = || DDSketch2::logarithmic_high(dd2_err);
 
             println!(
-                "\nCOUNT={}, TDIGEST_BATCH={}, TDIGEST_MAX_SIZE={}, HDR_SIGFIG={}, DDSketch2Err={}",
-                pretty_print_count(&count_group),
+                "\nCOUNT={}, CKMS_ERROR={}, GK_ERROR={}, ZW_ERROR={}, TDIGEST_BATCH={}, TDIGEST_MAX_SIZE={}, HDR_SIGFIG={}, DDSketch2Err={}, PROM_SCHEMA={}, RESERVOIR_SIZE={}",
+                pretty_print_count(count_group),
+                ckms_error,
+                gk_error,
+                zw_error,
                 tdigest_batch.separate_with_underscores(),
                 tdigest_max_size,
                 hdr_sigfig,
                 dd2_err,
+                prom_schema,
+                reservoir_size.separate_with_underscores(),
             );
             let mut table = get_markdown_table();
-            table.set_titles(row![
-                "Distribution",
-                "Algorithm",
-                "Time",
-                "PeakMemory",
-                "SerializedSize",
-                "50.0",
-                "75.0",
-                "90.0",
-                "95.0",
-                "99.0",
-                "99.9",
-                "99.99"
-            ]);
-            test(&count_group, all, distribution, table.add_row(row![distr]));
-            //test(count, &mut ag, distribution, table.add_row(row![distr]));
-            //if count < 50_000_000 {
-            //test(count, &mut aq, distribution, table.add_row(row![distr]));
-            //}
-            test(&count_group, td, distribution, table.add_row(row![distr]));
-            //test(count, &mut zw, distribution, table.add_row(row![distr]));
-            test(&count_group, hdr, distribution, table.add_row(row![distr]));
-            test(&count_group, dd, distribution, table.add_row(row![distr]));
-            test(&count_group, dd2, distribution, table.add_row(row![distr]));
+            table.set_titles(get_result_titles());
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, ag, distribution, table.add_row(row![distr]));
+            // Extremely slow, usually stopped by the time budget for the larger counts.
+            test(count_group, aq, distribution, table.add_row(row![distr]));
+            test(count_group, td, distribution, table.add_row(row![distr]));
+            test(count_group, zw, distribution, table.add_row(row![distr]));
+            test(count_group, hdr, distribution, table.add_row(row![distr]));
+            test(
+                count_group,
+                hdr_scaled,
+                distribution,
+                table.add_row(row![distr]),
+            );
             test(
-                &count_group,
+                count_group,
+                hdr_double,
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(count_group, dd, distribution, table.add_row(row![distr]));
+            test(count_group, dd2, distribution, table.add_row(row![distr]));
+            test(
+                count_group,
                 quanto,
                 distribution,
                 table.add_row(row![distr]),
             );
-            //test(&count_group, dd3, distribution, table.add_row(row![distr]));
-            //test(&count_group, dd4, distribution, table.add_row(row![distr]));
+            test(count_group, prom, distribution, table.add_row(row![distr]));
+            test(count_group, circ, distribution, table.add_row(row![distr]));
+            test(
+                count_group,
+                reservoir,
+                distribution,
+                table.add_row(row![distr]),
+            );
+            //test(count_group, dd3, distribution, table.add_row(row![distr]));
+            //test(count_group, dd4, distribution, table.add_row(row![distr]));
 
             table.printstd();
         }
@@ -175,7 +307,7 @@ fn pretty_print_count(count_group: &[usize]) -> String {
 
 #[allow(dead_code)]
 fn test_sketch_params() {
-    let counts = vec![
+    let counts = [
         vec![1_000],
         vec![1_000_000],
         vec![3_000_000],
@@ -187,38 +319,25 @@ fn test_sketch_params() {
     let mut distributions = get_distributions();
 
     for (distr, distribution) in &mut distributions {
-        for count_group in counts.iter().cloned() {
+        for count_group in counts.iter() {
             let mut table = get_markdown_table();
-            let count_str = pretty_print_count(&count_group);
+            let count_str = pretty_print_count(count_group);
 
             println!("\nCOUNT={}", count_str);
 
-            table.set_titles(row![
-                "Distribution",
-                "Algorithm",
-                "Time",
-                "PeakMemory",
-                "SerializedSize",
-                "50.0",
-                "75.0",
-                "90.0",
-                "95.0",
-                "99.0",
-                "99.9",
-                "99.99"
-            ]);
+            table.set_titles(get_result_titles());
 
             let all = || AllValues::new();
 
-            test(&count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, all, distribution, table.add_row(row![distr]));
 
-            let err = vec![0.01, 0.02, 0.03, 0.05, 0.10, 0.2];
+            let err = [0.01, 0.02, 0.03, 0.05, 0.10, 0.2];
 
             for err_val in err.iter() {
                 let dd2 = || DDSketch2::unbounded(*err_val);
 
                 test(
-                    &count_group,
+                    count_group,
                     dd2,
                     distribution,
                     table.add_row(row![format!("ErrRate {}", err_val)]),
@@ -230,7 +349,7 @@ fn test_sketch_params() {
             //let mut dd2 = DDSketch2::new(*err_val, *num_bucket_val);
 
             //test(
-            //&count_group,
+            //count_group,
             //&mut dd2,
             //distribution,
             //table.add_row(row![format!("DD {}:{}", err_val, num_bucket_val)]),
@@ -241,6 +360,28 @@ fn test_sketch_params() {
     }
 }
 
+/// Titles matching the cells added by `test`.
+fn get_result_titles() -> Row {
+    row![
+        "Distribution",
+        "Algorithm",
+        "Time",
+        "MergeTime",
+        "PeakMemory",
+        "HeapSize",
+        "Allocated",
+        "SerializedSize",
+        "Negatives",
+        "50.0",
+        "75.0",
+        "90.0",
+        "95.0",
+        "99.0",
+        "99.9",
+        "99.99"
+    ]
+}
+
 fn get_markdown_table() -> Table {
     let mut table = Table::new();
 
@@ -258,7 +399,7 @@ fn get_markdown_table() -> Table {
 
 #[allow(dead_code)]
 fn test_digest_params() {
-    let counts = vec![
+    let counts = [
         vec![1_000],
         vec![1_000_000],
         vec![3_000_000],
@@ -268,37 +409,24 @@ fn test_digest_params() {
     let mut distributions = get_distributions();
 
     for (distr, distribution) in &mut distributions {
-        for count_group in counts.iter().cloned() {
+        for count_group in counts.iter() {
             let mut table = get_markdown_table();
-            let count_str = pretty_print_count(&count_group);
+            let count_str = pretty_print_count(count_group);
 
             println!("\nCOUNT={}", count_str);
 
-            table.set_titles(row![
-                "Distribution",
-                "Algorithm",
-                "Time",
-                "PeakMemory",
-                "SerializedSize",
-                "50.0",
-                "75.0",
-                "90.0",
-                "95.0",
-                "99.0",
-                "99.9",
-                "99.99"
-            ]);
+            table.set_titles(get_result_titles());
 
             let all = || AllValues::new();
 
-            test(&count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, all, distribution, table.add_row(row![distr]));
 
-            let batch_sizes = vec![100usize];
-            let max_sizes = vec![100, 200, 300, 500, 1000, 2000];
+            let batch_sizes = [100usize];
+            let max_sizes = [100, 200, 300, 500, 1000, 2000];
 
             for (batch, max_size) in batch_sizes.iter().cartesian_product(max_sizes.iter()) {
                 test(
-                    &count_group,
+                    count_group,
                     || TDigest::new(*batch, *max_size),
                     distribution,
                     table.add_row(row![format!("Batch:T-Size {}:{}", batch, max_size)]),
@@ -309,502 +437,3573 @@ fn test_digest_params() {
     }
 }
 
-trait Aggregate {
-    fn name(&self) -> &str;
-    fn finalize(&mut self) {}
-    fn get_quantil(&mut self, q: f64) -> f64;
-    fn insert(&mut self, value: f64);
+#[allow(dead_code)]
+fn test_native_histogram_params() {
+    let counts = [
+        vec![1_000],
+        vec![1_000_000],
+        vec![1_000, 3_000_000, 1_000_000],
+    ];
 
-    fn serialize_size(&self) -> usize {
-        0
-    }
+    let mut distributions = get_distributions();
 
-    // Default implementation which covers the nothing to merge case
-    fn merge(mut other: Vec<Self>) -> Option<Self>
-    where
-        Self: Sized,
-    {
-        if other.len() == 1 {
-            return other.pop();
-        }
-        None
-    }
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
 
-    fn get_percentiles(&mut self) -> Percentiles {
-        self.finalize();
-        SELECTED_PERCENTILES
-            .iter()
-            .cloned()
-            .map(|percentil| Percentile::from(self.get_quantil(percentil / 100.0)))
-            .collect::<Vec<Percentile>>()
-    }
-}
+            println!("\nCOUNT={}", count_str);
 
-const SELECTED_PERCENTILES: [f64; 7] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 99.99];
+            table.set_titles(get_result_titles());
 
-type Percentiles = Vec<Percentile>;
-struct Percentile {
-    value: f64,
-}
-impl From<f64> for Percentile {
-    fn from(value: f64) -> Self {
-        Self { value }
+            let all = || AllValues::new();
+
+            test(count_group, all, distribution, table.add_row(row![distr]));
+
+            let schemas = [0, 2, 3, 5, 8];
+            let zero_thresholds = [native_histogram::DEFAULT_ZERO_THRESHOLD, 0.001];
+
+            for (schema, zero_threshold) in schemas.iter().cartesian_product(zero_thresholds.iter())
+            {
+                test(
+                    count_group,
+                    || PromNativeHistogram::new(*schema, *zero_threshold),
+                    distribution,
+                    table.add_row(row![format!(
+                        "Schema:ZeroThr {}:{}",
+                        schema, zero_threshold
+                    )]),
+                );
+            }
+            // client_golang reduces the schema once the bucket limit is hit (default 160).
+            test(
+                count_group,
+                || {
+                    PromNativeHistogram::with_max_buckets(
+                        8,
+                        native_histogram::DEFAULT_ZERO_THRESHOLD,
+                        160,
+                    )
+                },
+                distribution,
+                table.add_row(row!["Schema:MaxBuckets 8:160"]),
+            );
+            table.printstd();
+        }
     }
 }
 
+/// Compares fixed bucket layouts against TDigest and DDSketch on the data sets with a long tail.
 #[allow(dead_code)]
-#[derive(Default)]
-struct TestResult {
-    pub name: String,
-    pub run_time: f64,
-    pub memory: usize,
-    pub percentiles: Percentiles,
-}
-
-///
-fn test<A: Aggregate, F: Fn() -> A>(
-    count_group: &[usize],
-    aggregate: F,
-    sampler: &mut Box<dyn FnMut(usize) -> f64>,
-    row: &mut Row,
-) -> TestResult {
-    let start = std::time::Instant::now();
-    GLOBAL.reset_peak_memory();
+fn test_classic_histogram_buckets() {
+    let counts = [vec![1_000_000], vec![1_000, 3_000_000, 1_000_000]];
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
 
-    #[cfg(feature = "parallel-collect")]
-    let aggregates = {
-        let mut iters = count_group
-            .iter()
-            .map(|count| (0..*count, aggregate()))
-            .collect::<Vec<_>>();
-        let mut finished_aggregates = Vec::new();
-        let mut iter_index = 0;
-        loop {
-            let iter = &mut iters[iter_index];
+    let mut distributions = get_distributions();
 
-            if let Some(i) = iter.0.next() {
-                let value = sampler(i);
-                iter.1.insert(value);
-            } else {
-                let mut aggregate = iters.remove(iter_index).1;
-                aggregate.finalize();
-                finished_aggregates.push(aggregate);
-                if iters.is_empty() {
-                    break;
-                }
-            }
+    for (distr, distribution) in distributions
+        .iter_mut()
+        .filter(|(distr, _)| distr.starts_with("LogNorm") || distr.starts_with("PM10"))
+    {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
 
-            iter_index += 1;
-            iter_index %= iters.len();
-        }
-        finished_aggregates
-    };
-    #[cfg(not(feature = "parallel-collect"))]
-    let aggregates = {
-        count_group
-            .iter()
-            .map(|count| {
-                let mut aggregate = aggregate();
-                for i in 0..*count {
-                    let value = sampler(i);
-                    aggregate.insert(value);
-                }
-                aggregate.finalize();
-                aggregate
-            })
-            .collect::<Vec<_>>()
-    };
-    let name = aggregates[0].name().to_string();
-    let mut aggregate = if let Some(aggregate) = A::merge(aggregates) {
-        aggregate
-    } else {
-        // Unsupported
-        // Fill cells
-        row.add_cell(Cell::new(&name));
-        for _ in 1..4 + SELECTED_PERCENTILES.len() {
-            row.add_cell(Cell::new(&"NaN"));
-        }
+            println!("\nCOUNT={}", count_str);
 
-        return TestResult::default();
-    };
+            table.set_titles(get_result_titles());
 
-    let percentiles = aggregate.get_percentiles();
-    let elapsed = start.elapsed().as_secs_f64();
-    let peak_memory = GLOBAL.get_peak_memory();
+            let all = || AllValues::new();
+            let td = || TDigest::new(tdigest_batch, tdigest_max_size);
+            let dd = || DDSketch::new();
 
-    for entry in [
-        aggregate.name().to_owned(),
-        format!("{:.3}s", elapsed),
-        format!("{}k ", peak_memory / 1024),
-        pretty_print_ser_size(aggregate.serialize_size()),
-    ] {
-        row.add_cell(Cell::new(&entry));
-    }
-    for percentile in percentiles {
-        row.add_cell(Cell::new(&format!("{:.2}", percentile.value)));
-    }
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, td, distribution, table.add_row(row![distr]));
+            test(count_group, dd, distribution, table.add_row(row![distr]));
 
-    TestResult {
-        name: aggregate.name().to_string(),
-        run_time: elapsed,
-        memory: peak_memory,
-        percentiles: aggregate.get_percentiles(),
+            let layouts: [ClassicLayout; 5] = [
+                (
+                    "DefBuckets",
+                    Box::new(|| ClassicHistogram::new(classic_histogram::DEFAULT_BUCKETS.to_vec())),
+                ),
+                (
+                    "Linear 10+10x50",
+                    Box::new(|| ClassicHistogram::linear(10.0, 10.0, 50)),
+                ),
+                (
+                    "Exp 1*2x16",
+                    Box::new(|| ClassicHistogram::exponential(1.0, 2.0, 16)),
+                ),
+                (
+                    "Exp 1*2x24",
+                    Box::new(|| ClassicHistogram::exponential(1.0, 2.0, 24)),
+                ),
+                (
+                    "Exp 1*1.25x64",
+                    Box::new(|| ClassicHistogram::exponential(1.0, 1.25, 64)),
+                ),
+            ];
+            for (label, classic) in layouts.iter() {
+                test(
+                    count_group,
+                    classic,
+                    distribution,
+                    table.add_row(row![label]),
+                );
+            }
+            table.printstd();
+        }
     }
 }
 
-fn pretty_print_ser_size(size: usize) -> String {
-    match size {
-        0 => "unavailable".to_string(),
-        1..=2000 => format!("{}", size),
-        _ => format!("{}k", size / 1024),
-    }
-}
+#[allow(dead_code)]
+fn test_integer_data() {
+    let counts = [
+        vec![1_000],
+        vec![1_000_000],
+        vec![1_000, 3_000_000, 1_000_000],
+    ];
+    let qdigest_universe_bits = 32;
+    let hdr_sigfig = 2;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
 
-struct AllValues {
-    values: Vec<f64>,
-}
-impl AllValues {
-    fn new() -> Self {
-        let values = Vec::new();
-        AllValues { values }
-    }
-}
-impl Aggregate for AllValues {
-    fn name(&self) -> &str {
-        "AllValues"
-    }
-    fn finalize(&mut self) {
-        self.values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    }
+    let mut distributions = get_integer_distributions();
 
-    fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
 
-        let index = (self.values.len() as f64 * q).ceil() as usize;
-        let index = index.min(self.values.len() - 1);
-        self.values[index] as f64
-    }
+            println!(
+                "\nCOUNT={}, QDIGEST_UNIVERSE=2^{}",
+                count_str, qdigest_universe_bits
+            );
 
-    fn insert(&mut self, value: f64) {
-        self.values.push(value);
-    }
+            table.set_titles(get_result_titles());
 
-    fn serialize_size(&self) -> usize {
-        self.values.len() * 8
-    }
+            let all = || AllValues::new();
+            let td = || TDigest::new(tdigest_batch, tdigest_max_size);
+            let hdr = || HDRHistogram::new(hdr_sigfig);
+            let dd = || DDSketch::new();
 
-    fn merge(mut other: Vec<Self>) -> Option<Self>
-    where
-        Self: Sized,
-    {
-        let mut first = other.pop().unwrap();
-        for el in other {
-            first.values.extend_from_slice(&el.values);
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, td, distribution, table.add_row(row![distr]));
+            test(count_group, hdr, distribution, table.add_row(row![distr]));
+            test(count_group, dd, distribution, table.add_row(row![distr]));
+
+            for compression in [10, 100, 1000] {
+                test(
+                    count_group,
+                    || QDigest::new(compression, qdigest_universe_bits),
+                    distribution,
+                    table.add_row(row![format!("Compression {}", compression)]),
+                );
+            }
+            table.printstd();
         }
-        Some(first)
     }
 }
 
-struct QuantilesCKMS {
-    q: quantiles::ckms::CKMS<f64>,
-}
-impl QuantilesCKMS {
-    #[allow(dead_code)]
-    fn new(error: f64) -> Self {
-        let q = quantiles::ckms::CKMS::new(error);
-        QuantilesCKMS { q }
-    }
+/// How random samples of different sizes compare to sketches, especially at the high percentiles.
+#[allow(dead_code)]
+fn test_reservoir_sizes() {
+    let counts = [
+        vec![1_000_000],
+        vec![5_000_000],
+        vec![1_000, 3_000_000, 1_000_000],
+    ];
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let dd2_err = 0.01;
+
+    let mut distributions = get_distributions();
+
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
+
+            println!("\nCOUNT={}", count_str);
+
+            table.set_titles(get_result_titles());
+
+            let all = || AllValues::new();
+            let td = || TDigest::new(tdigest_batch, tdigest_max_size);
+            let dd2 = || DDSketch2::unbounded(dd2_err);
+
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, td, distribution, table.add_row(row![distr]));
+            test(count_group, dd2, distribution, table.add_row(row![distr]));
+
+            for size in [1_000, 10_000, 100_000] {
+                let size_str = size.separate_with_underscores();
+                test(
+                    count_group,
+                    || Reservoir::new(size),
+                    distribution,
+                    table.add_row(row![format!("Reservoir size {}", size_str)]),
+                );
+                test(
+                    count_group,
+                    || WeightedReservoir::new(size),
+                    distribution,
+                    table.add_row(row![format!("WeightedReservoir size {}", size_str)]),
+                );
+            }
+            table.printstd();
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn test_hdr_float_params() {
+    let counts = [vec![1_000_000], vec![1_000, 3_000_000, 1_000_000]];
+    let hdr_sigfig = 2;
+
+    let mut distributions = get_distributions();
+
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
+
+            println!("\nCOUNT={}, HDR_SIGFIG={}", count_str, hdr_sigfig);
+
+            table.set_titles(get_result_titles());
+
+            let all = || AllValues::new();
+            let hdr = || HDRHistogram::new(hdr_sigfig);
+
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, hdr, distribution, table.add_row(row![distr]));
+
+            test(
+                count_group,
+                || HDRScaledHistogram::auto(hdr_sigfig),
+                distribution,
+                table.add_row(row!["Scale auto"]),
+            );
+            for scale in [10.0, 1_000.0, 1_000_000.0] {
+                test(
+                    count_group,
+                    || HDRScaledHistogram::with_scale(hdr_sigfig, scale),
+                    distribution,
+                    table.add_row(row![format!("Scale {}", scale)]),
+                );
+            }
+            for range_bits in [24, 32, 48] {
+                test(
+                    count_group,
+                    || HDRDoubleHistogram::new(range_bits, hdr_sigfig),
+                    distribution,
+                    table.add_row(row![format!("Range 2^{}", range_bits)]),
+                );
+            }
+            table.printstd();
+        }
+    }
+}
+
+/// Runs every aggregate on negative and mixed-sign data. Backends without support for negative
+/// values run as is and wrapped in `Mirrored`.
+#[allow(dead_code)]
+fn test_signed_values() {
+    let counts = [vec![1_000_000], vec![1_000, 3_000_000, 1_000_000]];
+    let hdr_sigfig = 2;
+    let hdr_range_bits = 40;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let dd2_err = 0.01;
+    let prom_schema = 3;
+    let qdigest_universe_bits = 32;
+
+    let mut distributions = get_signed_distributions();
+
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            let count_str = pretty_print_count(count_group);
+
+            println!("\nCOUNT={}", count_str);
+
+            table.set_titles(get_result_titles());
+
+            let all = || AllValues::new();
+            let td = || TDigest::new(tdigest_batch, tdigest_max_size);
+            let hdr = || HDRHistogram::new(hdr_sigfig);
+            let hdr_scaled = || HDRScaledHistogram::auto(hdr_sigfig);
+            let hdr_double = || HDRDoubleHistogram::new(hdr_range_bits, hdr_sigfig);
+            let dd = || DDSketch::new();
+            let dd2 = || DDSketch2::unbounded(dd2_err);
+            let quanto = || Quantogram::new();
+            let prom =
+                || PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD);
+            let circ = || Circllhist::new();
+            let qdigest = || QDigest::new(100, qdigest_universe_bits);
+
+            test(count_group, all, distribution, table.add_row(row![distr]));
+            test(count_group, td, distribution, table.add_row(row![distr]));
+            test(count_group, dd, distribution, table.add_row(row![distr]));
+            test(count_group, dd2, distribution, table.add_row(row![distr]));
+            test(
+                count_group,
+                quanto,
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(count_group, prom, distribution, table.add_row(row![distr]));
+            test(count_group, circ, distribution, table.add_row(row![distr]));
+
+            test(count_group, hdr, distribution, table.add_row(row![distr]));
+            test(
+                count_group,
+                || Mirrored::new(hdr),
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(
+                count_group,
+                hdr_scaled,
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(
+                count_group,
+                || Mirrored::new(hdr_scaled),
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(
+                count_group,
+                hdr_double,
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(
+                count_group,
+                || Mirrored::new(hdr_double),
+                distribution,
+                table.add_row(row![distr]),
+            );
+            test(
+                count_group,
+                || Mirrored::new(qdigest),
+                distribution,
+                table.add_row(row![distr]),
+            );
+            table.printstd();
+        }
+    }
+}
+
+/// Feeds NaN, infinite and subnormal values to every backend, wrapped in `Validated` with each
+/// policy.
+#[allow(dead_code)]
+fn test_invalid_values() {
+    let count = 100_000;
+    let special_values = [
+        f64::NAN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::from_bits(1), // smallest subnormal
+        -f64::MIN_POSITIVE / 2.0,
+        0.0,
+        -0.0,
+    ];
+    let policies = [
+        InvalidValuePolicy::Reject,
+        InvalidValuePolicy::Skip,
+        InvalidValuePolicy::Clamp {
+            min: -1e12,
+            max: 1e12,
+        },
+    ];
+    let hdr_sigfig = 2;
+    let hdr_range_bits = 40;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let dd2_err = 0.01;
+    let prom_schema = 3;
+
+    let mut distributions = get_distributions();
+    let (distr, distribution) = &mut distributions[2];
+
+    let mut table = get_markdown_table();
+    table.set_titles(row![
+        "Distribution",
+        "Algorithm",
+        "Policy",
+        "Rejected",
+        "Skipped",
+        "Clamped",
+        "50.0",
+        "99.0",
+        "99.99"
+    ]);
+
+    fn run<A: Aggregate>(aggregate: A, policy: InvalidValuePolicy, values: &[f64], row: &mut Row) {
+        let mut validated = Validated::new(aggregate, policy);
+        let mut rejected = 0;
+        for value in values {
+            if validated.try_insert(*value).is_err() {
+                rejected += 1;
+            }
+        }
+        validated.finalize();
+        row.add_cell(Cell::new(validated.name()));
+        for entry in [
+            policy.to_string(),
+            rejected.to_string(),
+            validated.skipped.to_string(),
+            validated.clamped.to_string(),
+        ] {
+            row.add_cell(Cell::new(&entry));
+        }
+        for q in [0.5, 0.99, 0.9999] {
+            row.add_cell(Cell::new(&format!("{:.2}", validated.get_quantil(q))));
+        }
+    }
+
+    // Every 100th value is a special value.
+    let values: Vec<f64> = (0..count)
+        .map(|i| {
+            if i % 100 == 99 {
+                special_values[(i / 100) % special_values.len()]
+            } else {
+                distribution(i)
+            }
+        })
+        .collect();
+
+    for policy in policies {
+        run(
+            AllValues::new(),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            TDigest::new(tdigest_batch, tdigest_max_size),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            HDRHistogram::new(hdr_sigfig),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            HDRScaledHistogram::auto(hdr_sigfig),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            HDRDoubleHistogram::new(hdr_range_bits, hdr_sigfig),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(DDSketch::new(), policy, &values, table.add_row(row![distr]));
+        run(
+            DDSketch2::unbounded(dd2_err),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            Quantogram::new(),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            Circllhist::new(),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            QDigest::new(100, 32),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            Reservoir::new(10_000),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            QuantilesGK::new(0.001),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            QuantilesCKMS::new(0.001),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+        run(
+            ZWQuantile::new(0.001),
+            policy,
+            &values,
+            table.add_row(row![distr]),
+        );
+    }
+    table.printstd();
+}
+
+/// Serializes every backend, deserializes it again and compares the percentiles of both.
+#[allow(dead_code)]
+fn test_serialization_round_trip() {
+    let count = 100_000;
+    let hdr_sigfig = 2;
+    let hdr_range_bits = 40;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let dd2_err = 0.01;
+    let prom_schema = 3;
+    let error = 0.001;
+
+    fn round_trip<A: Aggregate>(mut aggregate: A, values: &[f64], row: &mut Row) {
+        for value in values {
+            aggregate.insert(*value);
+        }
+        let expected = aggregate.get_percentiles();
+        let start = std::time::Instant::now();
+        let bytes = aggregate.serialize();
+        let serialize_time = start.elapsed();
+        let start = std::time::Instant::now();
+        let decoded = A::deserialize(&bytes);
+        let deserialize_time = start.elapsed();
+
+        let result = match decoded {
+            Ok(mut decoded) => describe_deviation(&decoded.get_percentiles(), &expected),
+            Err(err) => format!("FAILED: {}", err),
+        };
+        for entry in [
+            aggregate.name().to_string(),
+            serialized_size_cell(&aggregate, bytes.len()),
+            format!("{:.3}ms", serialize_time.as_secs_f64() * 1000.0),
+            format!("{:.3}ms", deserialize_time.as_secs_f64() * 1000.0),
+            result,
+        ] {
+            row.add_cell(Cell::new(&entry));
+        }
+    }
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..count).map(&mut *distribution).collect();
+        println!("\nCOUNT={}", count.separate_with_underscores());
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "SerializedSize",
+            "Serialize",
+            "Deserialize",
+            "Percentiles"
+        ]);
+        round_trip(AllValues::new(), &values, table.add_row(row![distr]));
+        round_trip(QuantilesGK::new(error), &values, table.add_row(row![distr]));
+        round_trip(
+            QuantilesCKMS::new(error),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            TDigest::new(tdigest_batch, tdigest_max_size),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(ZWQuantile::new(error), &values, table.add_row(row![distr]));
+        round_trip(
+            HDRHistogram::new(hdr_sigfig),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            HDRScaledHistogram::auto(hdr_sigfig),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            HDRDoubleHistogram::new(hdr_range_bits, hdr_sigfig),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(DDSketch::new(), &values, table.add_row(row![distr]));
+        round_trip(
+            DDSketch2::unbounded(dd2_err),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(Quantogram::new(), &values, table.add_row(row![distr]));
+        round_trip(
+            PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            ClassicHistogram::new(classic_histogram::DEFAULT_BUCKETS.to_vec()),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(Circllhist::new(), &values, table.add_row(row![distr]));
+        round_trip(QDigest::new(100, 32), &values, table.add_row(row![distr]));
+        round_trip(Reservoir::new(10_000), &values, table.add_row(row![distr]));
+        round_trip(
+            WeightedReservoir::new(10_000),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            Mirrored::new(|| HDRHistogram::new(hdr_sigfig)),
+            &values,
+            table.add_row(row![distr]),
+        );
+        round_trip(
+            Validated::new(Circllhist::new(), InvalidValuePolicy::Skip),
+            &values,
+            table.add_row(row![distr]),
+        );
+        table.printstd();
+    }
+}
+
+/// Writes an interval log with a histogram per interval and reads it back. Reports the average
+/// size of an interval as V2, deflate compressed V2 and log line. With `HDR_LOG` set, the log at
+/// that path is read as well.
+#[allow(dead_code)]
+fn test_hdr_interval_log() {
+    let intervals = 60;
+    let count_per_interval = 10_000;
+    let hdr_sigfig = 2;
+
+    fn summarize(
+        name: &str,
+        intervals: &[hdr_log::Interval],
+        log_size: usize,
+        read_back: &str,
+        table: &mut Table,
+    ) {
+        let mut v2_size = 0;
+        let mut deflate_size = 0;
+        let mut values = 0;
+        for interval in intervals {
+            let mut aggregate = HDRHistogram {
+                histogram: interval.histogram.clone(),
+            };
+            v2_size += aggregate.encode(Encoding::Native).unwrap().len();
+            deflate_size += aggregate.encode(Encoding::V2Deflate).unwrap().len();
+            values += interval.histogram.len() as usize;
+        }
+        let average = |size: usize| pretty_print_ser_size(size / intervals.len().max(1));
+        let row = table.add_row(row![name]);
+        for entry in [
+            intervals.len().to_string(),
+            values.separate_with_underscores(),
+            average(v2_size),
+            average(deflate_size),
+            average(log_size),
+            read_back.to_string(),
+        ] {
+            row.add_cell(Cell::new(&entry));
+        }
+    }
+
+    let mut table = get_markdown_table();
+    table.set_titles(row![
+        "Log",
+        "Intervals",
+        "Values",
+        "V2",
+        "V2 Deflate",
+        "Log Line",
+        "Read Back"
+    ]);
+    for (distr, distribution) in &mut get_distributions() {
+        let written: Vec<hdr_log::Interval> = (0..intervals)
+            .map(|interval| {
+                let mut aggregate = HDRHistogram::new(hdr_sigfig);
+                for index in 0..count_per_interval {
+                    aggregate.insert(distribution(interval * count_per_interval + index));
+                }
+                hdr_log::Interval {
+                    start: std::time::Duration::from_secs(interval as u64),
+                    duration: std::time::Duration::from_secs(1),
+                    histogram: aggregate.histogram,
+                }
+            })
+            .collect();
+        let log = hdr_log::write(&written).unwrap();
+        let read_back = match hdr_log::read(&log) {
+            Ok(read) if read.len() == written.len() => {
+                let identical = read
+                    .iter()
+                    .zip(written.iter())
+                    .filter(|(read, written)| {
+                        read.start == written.start && read.histogram == written.histogram
+                    })
+                    .count();
+                format!("{} identical", identical)
+            }
+            Ok(read) => format!("{} of {} intervals", read.len(), written.len()),
+            Err(err) => format!("FAILED: {}", err),
+        };
+        summarize(distr, &written, log.len(), &read_back, &mut table);
+    }
+    if let Ok(path) = std::env::var("HDR_LOG") {
+        let log = std::fs::read(&path).unwrap();
+        let intervals = hdr_log::read(&log).unwrap();
+        let replayed = hdr_log::replay(&intervals).len();
+        let read_back = format!("{} values replayed", replayed.separate_with_underscores());
+        summarize(&path, &intervals, log.len(), &read_back, &mut table);
+    }
+    table.printstd();
+}
+
+/// Size and encode and decode time of every encoding an algorithm supports, raw and deflate
+/// compressed.
+#[allow(dead_code)]
+fn test_encodings() {
+    let count = 100_000;
+    let hdr_sigfig = 2;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let dd2_err = 0.01;
+    let prom_schema = 3;
+    let error = 0.001;
+
+    fn deflate(data: &[u8], level: flate2::Compression) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), level);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::DeflateDecoder::new(data)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn millis(duration: std::time::Duration) -> String {
+        format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+    }
+
+    fn run<A: Aggregate>(mut aggregate: A, values: &[f64], distr: &str, table: &mut Table) {
+        for value in values {
+            aggregate.insert(*value);
+        }
+        aggregate.finalize();
+        for encoding in [
+            Encoding::Native,
+            Encoding::Json,
+            Encoding::Bincode,
+            Encoding::Varint,
+            Encoding::Protobuf,
+            Encoding::V2Deflate,
+        ] {
+            let start = std::time::Instant::now();
+            let Some(bytes) = aggregate.encode(encoding) else {
+                continue;
+            };
+            let encode_time = start.elapsed();
+            let start = std::time::Instant::now();
+            let decode = match A::decode(encoding, &bytes) {
+                Ok(_) => millis(start.elapsed()),
+                Err(err) => format!("FAILED: {}", err),
+            };
+            let fast = deflate(&bytes, flate2::Compression::fast());
+            let best = deflate(&bytes, flate2::Compression::best());
+            let start = std::time::Instant::now();
+            let inflated_decode = match A::decode(encoding, &inflate(&best)) {
+                Ok(_) => millis(start.elapsed()),
+                Err(err) => format!("FAILED: {}", err),
+            };
+            let row = table.add_row(row![distr]);
+            for entry in [
+                aggregate.name().to_string(),
+                encoding.to_string(),
+                serialized_size_cell(&aggregate, bytes.len()),
+                millis(encode_time),
+                decode,
+                pretty_print_ser_size(fast.len()),
+                pretty_print_ser_size(best.len()),
+                inflated_decode,
+            ] {
+                row.add_cell(Cell::new(&entry));
+            }
+        }
+    }
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..count).map(&mut *distribution).collect();
+        println!("\nCOUNT={}", count.separate_with_underscores());
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Encoding",
+            "Size",
+            "Encode",
+            "Decode",
+            "Deflate Fast",
+            "Deflate Best",
+            "Inflate + Decode"
+        ]);
+        let distr: &str = distr;
+        run(AllValues::new(), &values, distr, &mut table);
+        run(QuantilesGK::new(error), &values, distr, &mut table);
+        run(QuantilesCKMS::new(error), &values, distr, &mut table);
+        run(
+            TDigest::new(tdigest_batch, tdigest_max_size),
+            &values,
+            distr,
+            &mut table,
+        );
+        run(ZWQuantile::new(error), &values, distr, &mut table);
+        run(HDRHistogram::new(hdr_sigfig), &values, distr, &mut table);
+        run(DDSketch::new(), &values, distr, &mut table);
+        run(DDSketch2::unbounded(dd2_err), &values, distr, &mut table);
+        run(Quantogram::new(), &values, distr, &mut table);
+        run(
+            PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD),
+            &values,
+            distr,
+            &mut table,
+        );
+        run(
+            ClassicHistogram::new(classic_histogram::DEFAULT_BUCKETS.to_vec()),
+            &values,
+            distr,
+            &mut table,
+        );
+        run(Circllhist::new(), &values, distr, &mut table);
+        run(QDigest::new(100, 32), &values, distr, &mut table);
+        run(Reservoir::new(10_000), &values, distr, &mut table);
+        run(WeightedReservoir::new(10_000), &values, distr, &mut table);
+        table.printstd();
+    }
+}
+
+/// "identical" or the largest relative deviation of decoded percentiles from the original ones.
+fn describe_deviation(actual: &[Percentile], expected: &[Percentile]) -> String {
+    let max_deviation = max_relative_error(actual, expected);
+    if max_deviation == 0.0 {
+        "identical".to_string()
+    } else {
+        format!("max deviation {:.4}%", max_deviation * 100.0)
+    }
+}
+
+/// The largest relative error over the percentiles, 0 for equal values.
+fn max_relative_error(actual: &[Percentile], expected: &[Percentile]) -> f64 {
+    actual
+        .iter()
+        .zip(expected.iter())
+        .map(|(actual, expected)| {
+            if actual.value == expected.value {
+                0.0
+            } else {
+                ((actual.value - expected.value) / expected.value).abs()
+            }
+        })
+        .fold(0f64, f64::max)
+}
+
+/// "p99 over the last 5 minutes": replays 30 minutes of values, one per millisecond, and queries
+/// the windowed aggregates every 10 seconds. The values rise slowly, so stale windows show up as
+/// error. The error is measured against the exact percentiles of the last 5 minutes, for the
+/// tumbling and the sliding window alike.
+#[allow(dead_code)]
+fn test_windowed_quantiles() {
+    let duration = std::time::Duration::from_secs(30 * 60);
+    let window = std::time::Duration::from_secs(5 * 60);
+    let query_interval = std::time::Duration::from_secs(10);
+    let value_interval = std::time::Duration::from_millis(1);
+    let slots = 10;
+    let gk_error = 0.001;
+    let tdigest_batch = 500;
+    let tdigest_max_size = 300;
+    let hdr_sigfig = 2;
+    let dd2_err = 0.01;
+    let prom_schema = 3;
+
+    let count = (duration.as_nanos() / value_interval.as_nanos()) as usize;
+    let per_window = (window.as_nanos() / value_interval.as_nanos()) as usize;
+    let per_query = (query_interval.as_nanos() / value_interval.as_nanos()) as usize;
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..count)
+            .map(|i| distribution(i) * (1.0 + i as f64 / count as f64))
+            .collect();
+        // The exact percentiles of the last window at every query.
+        let exact: Vec<Percentiles> = values
+            .chunks(per_query)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let end = index * per_query + chunk.len();
+                let mut exact = AllValues::new();
+                for value in &values[end.saturating_sub(per_window)..end] {
+                    exact.insert(*value);
+                }
+                exact.get_percentiles()
+            })
+            .collect();
+
+        println!(
+            "\nDURATION={}s, WINDOW={}s, SLOTS={}, QUERY_INTERVAL={}s, VALUE_INTERVAL={}ms",
+            duration.as_secs(),
+            window.as_secs(),
+            slots,
+            query_interval.as_secs(),
+            value_interval.as_millis(),
+        );
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Window",
+            "Values/s",
+            "Query",
+            "Mean Error",
+            "Max Error",
+            "Mean Error 99.0",
+        ]);
+
+        let gk = || QuantilesGK::new(gk_error);
+        let td = || TDigest::new(tdigest_batch, tdigest_max_size);
+        let hdr = || HDRHistogram::new(hdr_sigfig);
+        let dd = || DDSketch::new();
+        let dd2 = || DDSketch2::unbounded(dd2_err);
+        let prom =
+            || PromNativeHistogram::new(prom_schema, native_histogram::DEFAULT_ZERO_THRESHOLD);
+        let circ = || Circllhist::new();
+
+        let queries = WindowQueries {
+            values: &values,
+            value_interval,
+            per_query,
+            exact: &exact,
+        };
+        for kind in [WindowKind::Tumbling, WindowKind::Sliding { slots }] {
+            queries.run(Windowed::new(kind, window, gk), distr, &mut table);
+            queries.run(Windowed::new(kind, window, td), distr, &mut table);
+            queries.run(Windowed::new(kind, window, hdr), distr, &mut table);
+            queries.run(Windowed::new(kind, window, dd), distr, &mut table);
+            queries.run(Windowed::new(kind, window, dd2), distr, &mut table);
+            queries.run(Windowed::new(kind, window, prom), distr, &mut table);
+            queries.run(Windowed::new(kind, window, circ), distr, &mut table);
+        }
+        table.printstd();
+    }
+}
+
+/// The values of `test_windowed_quantiles`, one every `value_interval`, queried every
+/// `per_query` values.
+struct WindowQueries<'a> {
+    values: &'a [f64],
+    value_interval: std::time::Duration,
+    per_query: usize,
+    exact: &'a [Percentiles],
+}
+
+impl WindowQueries<'_> {
+    fn run<A: Aggregate + Clone, F: Fn() -> A>(
+        &self,
+        mut windowed: Windowed<A, F>,
+        distr: &str,
+        table: &mut Table,
+    ) {
+        let p99 = SELECTED_PERCENTILES
+            .iter()
+            .position(|percentile| *percentile == 99.0)
+            .unwrap();
+        let mut now = std::time::Duration::ZERO;
+        let mut insert_time = std::time::Duration::ZERO;
+        let mut query_time = std::time::Duration::ZERO;
+        let mut errors = Vec::with_capacity(self.exact.len());
+        let mut p99_errors = Vec::with_capacity(self.exact.len());
+        for (chunk, exact) in self.values.chunks(self.per_query).zip(self.exact) {
+            let start = std::time::Instant::now();
+            for value in chunk {
+                windowed.advance(now);
+                windowed.insert(*value);
+                now += self.value_interval;
+            }
+            insert_time += start.elapsed();
+
+            let start = std::time::Instant::now();
+            windowed.advance(now);
+            // A window without values, e.g. before the first rotation, has no answer.
+            let Some(window) = windowed.window() else {
+                continue;
+            };
+            let percentiles = window.get_percentiles();
+            query_time += start.elapsed();
+            errors.push(max_relative_error(&percentiles, exact));
+            p99_errors.push(max_relative_error(
+                &percentiles[p99..=p99],
+                &exact[p99..=p99],
+            ));
+        }
+
+        let mean = |errors: &[f64]| errors.iter().sum::<f64>() / errors.len() as f64;
+        let max_error = errors.iter().cloned().fold(0f64, f64::max);
+        table.add_row(row![
+            distr,
+            windowed.name(),
+            windowed.kind,
+            format!(
+                "{:.0}",
+                self.values.len() as f64 / insert_time.as_secs_f64()
+            ),
+            format!(
+                "{:.3}ms",
+                query_time.as_secs_f64() * 1000.0 / errors.len() as f64
+            ),
+            format!("{:.4}%", mean(&errors) * 100.0),
+            format!("{:.4}%", max_error * 100.0),
+            format!("{:.4}%", mean(&p99_errors) * 100.0),
+        ]);
+    }
+}
+
+/// Replays 20 minutes of values, one every 10ms, which double after 10 minutes. The forward
+/// decayed aggregates are queried every 10 seconds. Reports the error against the exact decayed
+/// percentiles, and how long after the shift the median and the 99th percentile are within 5% of
+/// the percentiles of the new values.
+#[allow(dead_code)]
+fn test_decayed_quantiles() {
+    let duration = std::time::Duration::from_secs(20 * 60);
+    let shift = std::time::Duration::from_secs(10 * 60);
+    let query_interval = std::time::Duration::from_secs(10);
+    let value_interval = std::time::Duration::from_millis(10);
+    let half_lives = [
+        Some(std::time::Duration::from_secs(30)),
+        Some(std::time::Duration::from_secs(120)),
+        None,
+    ];
+    let reservoir_size = 10_000;
+    let dd2_err = 0.01;
+
+    let count = (duration.as_nanos() / value_interval.as_nanos()) as usize;
+    let shift_index = (shift.as_nanos() / value_interval.as_nanos()) as usize;
+    let per_query = (query_interval.as_nanos() / value_interval.as_nanos()) as usize;
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..count)
+            .map(|i| distribution(i) * if i < shift_index { 1.0 } else { 2.0 })
+            .collect();
+        let mut shifted = AllValues::new();
+        for value in &values[shift_index..] {
+            shifted.insert(*value);
+        }
+        let queries = DecayQueries {
+            values: &values,
+            value_interval,
+            per_query,
+            shift_index,
+            target: shifted.get_percentiles(),
+        };
+
+        println!(
+            "\nDURATION={}s, SHIFT={}s, QUERY_INTERVAL={}s, VALUE_INTERVAL={}ms",
+            duration.as_secs(),
+            shift.as_secs(),
+            query_interval.as_secs(),
+            value_interval.as_millis(),
+        );
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Mean Error",
+            "Max Error",
+            "50.0 Tracking Time",
+            "99.0 Tracking Time",
+            "Weighted Quantile",
+        ]);
+        for half_life in half_lives {
+            let reference = queries.decayed_percentiles(half_life);
+            queries.run(
+                Decayed::new(WeightedReservoir::new(reservoir_size), half_life),
+                &reference,
+                "approximate, unweighted over the sample",
+                distr,
+                &mut table,
+            );
+            queries.run(
+                Decayed::new(DDSketch2::unbounded(dd2_err), half_life),
+                &reference,
+                "weighted bins",
+                distr,
+                &mut table,
+            );
+        }
+        table.printstd();
+    }
+}
+
+/// The values of `test_decayed_quantiles`, one every `value_interval`, queried every `per_query`
+/// values. `target` are the percentiles of the values after the shift.
+struct DecayQueries<'a> {
+    values: &'a [f64],
+    value_interval: std::time::Duration,
+    per_query: usize,
+    shift_index: usize,
+    target: Percentiles,
+}
+
+impl DecayQueries<'_> {
+    /// The exact decayed percentiles at every query. The weights are relative to the query time,
+    /// which has the same ratios as forward decay.
+    fn decayed_percentiles(&self, half_life: Option<std::time::Duration>) -> Vec<Percentiles> {
+        let lambda = half_life.map_or(0.0, |half_life| {
+            std::f64::consts::LN_2 / half_life.as_secs_f64()
+        });
+        let interval = self.value_interval.as_secs_f64();
+        (self.per_query..=self.values.len())
+            .step_by(self.per_query)
+            .map(|end| {
+                let pairs = self.values[..end]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| (*value, (lambda * (i as f64 - end as f64) * interval).exp()))
+                    .collect();
+                weighted_percentiles(pairs)
+            })
+            .collect()
+    }
+
+    /// `quantile` describes how the backend answers weighted quantiles.
+    fn run<A: Aggregate>(
+        &self,
+        mut decayed: Decayed<A>,
+        reference: &[Percentiles],
+        quantile: &str,
+        distr: &str,
+        table: &mut Table,
+    ) {
+        let p50 = SELECTED_PERCENTILES
+            .iter()
+            .position(|percentile| *percentile == 50.0)
+            .unwrap();
+        let p99 = SELECTED_PERCENTILES
+            .iter()
+            .position(|percentile| *percentile == 99.0)
+            .unwrap();
+        let mut errors = Vec::with_capacity(reference.len());
+        let mut tracking = [None, None];
+        for ((index, chunk), reference) in self
+            .values
+            .chunks_exact(self.per_query)
+            .enumerate()
+            .zip(reference)
+        {
+            for (i, value) in chunk.iter().enumerate() {
+                decayed.advance(self.value_interval * (index * self.per_query + i) as u32);
+                decayed.insert(*value);
+            }
+            let percentiles = decayed.get_percentiles();
+            errors.push(max_relative_error(&percentiles, reference));
+
+            let end = (index + 1) * self.per_query;
+            if end <= self.shift_index {
+                continue;
+            }
+            for (tracked, position) in tracking.iter_mut().zip([p50, p99]) {
+                let error = max_relative_error(
+                    &percentiles[position..=position],
+                    &self.target[position..=position],
+                );
+                if tracked.is_none() && error <= 0.05 {
+                    *tracked = Some(self.value_interval * (end - self.shift_index) as u32);
+                }
+            }
+        }
+
+        let mean_error = errors.iter().sum::<f64>() / errors.len() as f64;
+        let max_error = errors.iter().cloned().fold(0f64, f64::max);
+        let tracking = tracking.map(|tracked| {
+            tracked.map_or("never".to_string(), |tracked| {
+                format!("{}s", tracked.as_secs())
+            })
+        });
+        table.add_row(row![
+            distr,
+            decayed.name(),
+            format!("{:.4}%", mean_error * 100.0),
+            format!("{:.4}%", max_error * 100.0),
+            tracking[0],
+            tracking[1],
+            quantile,
+        ]);
+    }
+}
+
+/// Percentiles of `(value, weight)` pairs: the smallest value whose cumulative weight reaches the
+/// rank of the percentile.
+fn weighted_percentiles(mut pairs: Vec<(f64, f64)>) -> Percentiles {
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let cumulative: Vec<f64> = pairs
+        .iter()
+        .scan(0.0, |sum, (_, weight)| {
+            *sum += weight;
+            Some(*sum)
+        })
+        .collect();
+    let total = cumulative.last().copied().unwrap_or(0.0);
+    SELECTED_PERCENTILES
+        .iter()
+        .map(|percentile| {
+            let rank = percentile / 100.0 * total;
+            let index = cumulative.partition_point(|sum| *sum < rank);
+            Percentile::from(pairs[index.min(pairs.len() - 1)].0)
+        })
+        .collect()
+}
+
+trait Aggregate {
+    fn name(&self) -> &str;
+    fn finalize(&mut self) {}
+    fn get_quantil(&mut self, q: f64) -> f64;
+    fn insert(&mut self, value: f64);
+    /// Inserts `value` with a real-valued weight, `false` if weights are not supported.
+    fn insert_weighted(&mut self, _value: f64, _weight: f64) -> bool {
+        false
+    }
+
+    /// Binary representation of the aggregate, empty if serialization is not supported.
+    fn serialize(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn deserialize(_data: &[u8]) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized,
+    {
+        Err("deserialization is not supported".into())
+    }
+    /// `true` if `serialize` does not store the state of the aggregate but an approximation of
+    /// it, see `RankSteps`. The deserialized aggregate is within the error bounds of the original.
+    fn lossy_serialization(&self) -> bool {
+        false
+    }
+    /// The aggregate in `encoding`, `None` if the encoding is not supported. `Native` is
+    /// `serialize`, `Json` and `Bincode` are `encode_state`, the other encodings `encode_format`.
+    fn encode(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        match encoding {
+            Encoding::Native => Some(self.serialize()),
+            Encoding::Json | Encoding::Bincode => self.encode_state(encoding),
+            Encoding::Varint | Encoding::Protobuf | Encoding::V2Deflate => {
+                self.encode_format(encoding)
+            }
+        }
+    }
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized,
+    {
+        match encoding {
+            Encoding::Native => Self::deserialize(data),
+            Encoding::Json | Encoding::Bincode => Self::decode_state(encoding, data),
+            Encoding::Varint | Encoding::Protobuf | Encoding::V2Deflate => {
+                Self::decode_format(encoding, data)
+            }
+        }
+    }
+    /// The serde state of the aggregate in `Json` or `Bincode`, see `encode_serde`.
+    fn encode_state(&mut self, _encoding: Encoding) -> Option<Vec<u8>> {
+        None
+    }
+    fn decode_state(_encoding: Encoding, _data: &[u8]) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized,
+    {
+        Err("encoding is not supported".into())
+    }
+    /// `Varint`, `Protobuf` and `V2Deflate`, the formats an aggregate implements itself.
+    fn encode_format(&mut self, _encoding: Encoding) -> Option<Vec<u8>> {
+        None
+    }
+    fn decode_format(_encoding: Encoding, _data: &[u8]) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized,
+    {
+        Err("encoding is not supported".into())
+    }
+
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Native
+    }
+
+    /// Heap memory of the aggregate computed from its collections, `None` if the internals are
+    /// not accessible.
+    fn heap_size(&self) -> Option<HeapSize> {
+        None
+    }
+
+    // Default implementation which covers the nothing to merge case
+    fn merge(mut other: Vec<Self>) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        if other.len() == 1 {
+            return other.pop();
+        }
+        None
+    }
+
+    fn get_percentiles(&mut self) -> Percentiles {
+        self.finalize();
+        SELECTED_PERCENTILES
+            .iter()
+            .cloned()
+            .map(|percentil| Percentile::from(self.get_quantil(percentil / 100.0)))
+            .collect::<Vec<Percentile>>()
+    }
+}
+
+/// Encodings compared by `test_encodings`. `Native` is the format of `Aggregate::serialize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Native,
+    Json,
+    Bincode,
+    /// Varints with delta encoded buckets or values.
+    Varint,
+    /// DataDog's `DDSketch` protobuf message, see `ddsketch_proto`.
+    Protobuf,
+    /// HdrHistogram's deflate compressed V2 format, as used in interval logs.
+    V2Deflate,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Encoding::Native => "native",
+            Encoding::Json => "JSON",
+            Encoding::Bincode => "bincode",
+            Encoding::Varint => "varint",
+            Encoding::Protobuf => "protobuf",
+            Encoding::V2Deflate => "V2 deflate",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// `Json` and `Bincode` of a serde type, `None` for the other encodings.
+fn encode_serde<T: Serialize>(value: &T, encoding: Encoding) -> Option<Vec<u8>> {
+    match encoding {
+        Encoding::Json => Some(serde_json::to_vec(value).unwrap()),
+        Encoding::Bincode => Some(bincode::serialize(value).unwrap()),
+        _ => None,
+    }
+}
+
+fn decode_serde<T: serde::de::DeserializeOwned>(
+    encoding: Encoding,
+    data: &[u8],
+) -> Result<T, Box<dyn std::error::Error>> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::from_slice(data)?),
+        Encoding::Bincode => Ok(bincode::deserialize(data)?),
+        _ => Err("not a serde encoding".into()),
+    }
+}
+
+/// How an aggregate deals with negative values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NegativeValues {
+    Native,
+    /// Negative values are recorded negated in a second instance, see `Mirrored`.
+    Mirrored,
+    /// Negative values are recorded as 0.
+    Clamped,
+}
+
+impl std::fmt::Display for NegativeValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            NegativeValues::Native => "native",
+            NegativeValues::Mirrored => "mirrored",
+            NegativeValues::Clamped => "clamped to 0",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+const SELECTED_PERCENTILES: [f64; 7] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 99.99];
+
+type Percentiles = Vec<Percentile>;
+struct Percentile {
+    value: f64,
+}
+impl From<f64> for Percentile {
+    fn from(value: f64) -> Self {
+        Self { value }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Default)]
+struct TestResult {
+    pub name: String,
+    pub run_time: f64,
+    pub memory: usize,
+    pub allocated: usize,
+    pub percentiles: Percentiles,
+}
+
+/// Runs `run_test`. A panic in the algorithm is caught and reported as a "FAILED" cell, so the
+/// remaining rows of the table are still produced.
+fn test<A: Aggregate, F: Fn() -> A>(
+    count_group: &[usize],
+    aggregate: F,
+    sampler: &mut Sampler,
+    row: &mut Row,
+) -> TestResult {
+    let mut cells = Row::empty();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_test(count_group, &aggregate, sampler, &mut cells)
+    }));
+    match result {
+        Ok(result) => {
+            for cell in cells.iter() {
+                row.add_cell(cell.clone());
+            }
+            result
+        }
+        Err(payload) => {
+            let name = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                aggregate().name().to_string()
+            }))
+            .unwrap_or_else(|_| "unknown".to_string());
+            row.add_cell(Cell::new(&name));
+            row.add_cell(Cell::new(&format!("FAILED: {}", panic_message(&payload))));
+            for _ in 2..8 + SELECTED_PERCENTILES.len() {
+                row.add_cell(Cell::new("-"));
+            }
+            TestResult {
+                name,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+const DEFAULT_TIME_BUDGET: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Time budget for a single `test` run, including all aggregates of the count group. Slow
+/// algorithms are abandoned after the budget is spent. `TIME_BUDGET_SECS` overrides the default.
+fn time_budget() -> std::time::Duration {
+    static BUDGET: std::sync::OnceLock<std::time::Duration> = std::sync::OnceLock::new();
+    *BUDGET.get_or_init(|| match std::env::var("TIME_BUDGET_SECS") {
+        Ok(secs) => std::time::Duration::from_secs_f64(
+            secs.parse()
+                .expect("TIME_BUDGET_SECS has to be a number of seconds"),
+        ),
+        Err(_) => DEFAULT_TIME_BUDGET,
+    })
+}
+
+/// While ingesting, the clock is only checked every 1024 values, to keep the overhead out of the
+/// measurement.
+fn is_over_budget(start: std::time::Instant, ingested: usize) -> bool {
+    ingested & 1023 == 0 && start.elapsed() > time_budget()
+}
+
+/// `finalize` and `merge` can't be interrupted, the budget is checked before and after them. A
+/// single call can still overrun the budget, but it is reported as a timeout and the rest of the
+/// run is skipped.
+fn is_over_budget_in(start: std::time::Instant) -> bool {
+    start.elapsed() > time_budget()
+}
+
+/// `stage` says where the budget ran out, e.g. "after 1_024 values" or "in merge".
+fn timeout(name: &str, stage: &str, start: std::time::Instant, row: &mut Row) -> TestResult {
+    row.add_cell(Cell::new(name));
+    row.add_cell(Cell::new(&format!("TIMEOUT {}", stage)));
+    for _ in 2..8 + SELECTED_PERCENTILES.len() {
+        row.add_cell(Cell::new("-"));
+    }
+    TestResult {
+        name: name.to_string(),
+        run_time: start.elapsed().as_secs_f64(),
+        ..Default::default()
+    }
+}
+
+fn values_stage(ingested: usize) -> String {
+    format!("after {} values", ingested.separate_with_underscores())
+}
+
+/// What `run_test` does with NaN and infinite values of a distribution, e.g. of a replayed log.
+const HARNESS_INVALID_VALUES: InvalidValuePolicy = InvalidValuePolicy::Skip;
+
+fn run_test<A: Aggregate, F: Fn() -> A>(
+    count_group: &[usize],
+    aggregate: F,
+    sampler: &mut Sampler,
+    row: &mut Row,
+) -> TestResult {
+    let aggregate = || Validated::new(aggregate(), HARNESS_INVALID_VALUES);
+    let start = std::time::Instant::now();
+    // The containers of the harness are allocated upfront, so only the aggregates are measured.
+    let allocated_before = GLOBAL.allocated();
+    let mut aggregates = Vec::with_capacity(count_group.len());
+    GLOBAL.reset_peak_memory();
+
+    #[cfg(feature = "parallel-collect")]
+    {
+        let mut iters = Vec::with_capacity(count_group.len());
+        iters.extend(count_group.iter().map(|count| (0..*count, aggregate())));
+        let mut iter_index = 0;
+        let mut ingested = 0;
+        loop {
+            let iter = &mut iters[iter_index];
+
+            if let Some(i) = iter.0.next() {
+                let value = sampler(i);
+                iter.1.insert(value);
+                ingested += 1;
+                if is_over_budget(start, ingested) {
+                    return timeout(iter.1.name(), &values_stage(ingested), start, row);
+                }
+            } else {
+                let mut aggregate = iters.remove(iter_index).1;
+                aggregate.finalize();
+                if is_over_budget_in(start) {
+                    return timeout(aggregate.name(), "in finalize", start, row);
+                }
+                aggregates.push(aggregate);
+                if iters.is_empty() {
+                    break;
+                }
+            }
+
+            iter_index += 1;
+            iter_index %= iters.len();
+        }
+    }
+    #[cfg(not(feature = "parallel-collect"))]
+    {
+        let mut ingested = 0;
+        for count in count_group {
+            let mut aggregate = aggregate();
+            for i in 0..*count {
+                let value = sampler(i);
+                aggregate.insert(value);
+                ingested += 1;
+                if is_over_budget(start, ingested) {
+                    return timeout(aggregate.name(), &values_stage(ingested), start, row);
+                }
+            }
+            aggregate.finalize();
+            if is_over_budget_in(start) {
+                return timeout(aggregate.name(), "in finalize", start, row);
+            }
+            aggregates.push(aggregate);
+        }
+    }
+    let name = aggregates[0].name().to_string();
+    let merge_start = std::time::Instant::now();
+    let merged = Validated::merge(aggregates);
+    let merge_time = merge_start.elapsed().as_secs_f64();
+    let mut aggregate = if let Some(aggregate) = merged {
+        aggregate
+    } else {
+        // Unsupported
+        // Fill cells
+        row.add_cell(Cell::new(&name));
+        for _ in 1..8 + SELECTED_PERCENTILES.len() {
+            row.add_cell(Cell::new("NaN"));
+        }
+
+        return TestResult::default();
+    };
+    if is_over_budget_in(start) {
+        return timeout(&name, "in merge", start, row);
+    }
+
+    let percentiles = aggregate.get_percentiles();
+    if is_over_budget_in(start) {
+        return timeout(&name, "in query", start, row);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let peak_memory = GLOBAL.get_peak_memory();
+    let allocated = GLOBAL.allocated().saturating_sub(allocated_before);
+    // The allocator delta also covers allocations outside of the collections, e.g. boxed nodes.
+    let heap_size = aggregate.heap_size();
+    let allocated_cell = match heap_size {
+        Some(heap_size) if heap_size.total() > 0 => format!(
+            "{}k ({:+.0}%)",
+            allocated / 1024,
+            (allocated as f64 / heap_size.total() as f64 - 1.0) * 100.0
+        ),
+        _ => format!("{}k ", allocated / 1024),
+    };
+    let heap_size = heap_size
+        .map(|heap_size| heap_size.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    // Without the header of `Validated`, the size is the one of the backend.
+    let serialized_size = aggregate.aggregate.serialize().len();
+
+    for entry in [
+        aggregate.name().to_owned(),
+        format!("{:.3}s", elapsed),
+        format!("{:.3}s", merge_time),
+        format!("{}k ", peak_memory / 1024),
+        heap_size,
+        allocated_cell,
+        serialized_size_cell(&aggregate, serialized_size),
+        aggregate.negative_values().to_string(),
+    ] {
+        row.add_cell(Cell::new(&entry));
+    }
+    for percentile in percentiles {
+        row.add_cell(Cell::new(&format!("{:.2}", percentile.value)));
+    }
+
+    TestResult {
+        name: aggregate.name().to_string(),
+        run_time: elapsed,
+        memory: peak_memory,
+        allocated,
+        percentiles: aggregate.get_percentiles(),
+    }
+}
+
+/// Lossy serializations are not comparable to the others, their size is marked.
+fn serialized_size_cell<A: Aggregate>(aggregate: &A, size: usize) -> String {
+    if aggregate.lossy_serialization() {
+        format!("{} (lossy)", pretty_print_ser_size(size))
+    } else {
+        pretty_print_ser_size(size)
+    }
+}
+
+fn pretty_print_ser_size(size: usize) -> String {
+    match size {
+        0 => "unavailable".to_string(),
+        1..=2000 => format!("{}", size),
+        _ => format!("{}k", size / 1024),
+    }
+}
+
+struct AllValues {
+    values: Vec<f64>,
+}
+impl AllValues {
+    fn new() -> Self {
+        let values = Vec::new();
+        AllValues { values }
+    }
+}
+impl Aggregate for AllValues {
+    fn name(&self) -> &str {
+        "AllValues"
+    }
+    fn finalize(&mut self) {
+        self.values.sort_by(|a, b| a.total_cmp(b));
+    }
+
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+
+        let index = (self.values.len() as f64 * q).ceil() as usize;
+        let index = index.min(self.values.len() - 1);
+        self.values[index]
+    }
+
+    fn insert(&mut self, value: f64) {
+        self.values.push(value);
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let chunks = data.chunks_exact(8);
+        if !chunks.remainder().is_empty() {
+            return Err(encoding::DecodeError::UnexpectedEof.into());
+        }
+        let values = chunks
+            .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        Ok(AllValues { values })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.values, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(AllValues {
+            values: decode_serde(encoding, data)?,
+        })
+    }
+    /// `Varint` stores the sorted values delta encoded.
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        if encoding != Encoding::Varint {
+            return None;
+        }
+        self.finalize();
+        let mut out = Vec::new();
+        encoding::put_sorted_f64s(&mut out, &self.values);
+        Some(out)
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if encoding != Encoding::Varint {
+            return Err("encoding is not supported".into());
+        }
+        let mut reader = encoding::Reader::new(data);
+        let values = reader.sorted_f64s()?;
+        reader.finish()?;
+        Ok(AllValues { values })
+    }
+
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(HeapSize::vec(&self.values))
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.values.extend_from_slice(&el.values);
+        }
+        Some(first)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuantilesCKMS {
+    q: quantiles::ckms::CKMS<f64>,
+    error: f64,
+    count: usize,
+}
+impl QuantilesCKMS {
+    #[allow(dead_code)]
+    fn new(error: f64) -> Self {
+        let q = quantiles::ckms::CKMS::new(error);
+        QuantilesCKMS { q, error, count: 0 }
+    }
+}
+impl Aggregate for QuantilesCKMS {
+    fn name(&self) -> &str {
+        "QuantilesCKMS"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.q.query(q).unwrap().1
+    }
+    fn insert(&mut self, value: f64) {
+        self.q.insert(value);
+        self.count += 1;
+    }
+
+    /// The state of the library with serde, in bincode.
+    fn serialize(&mut self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(data)?)
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(self, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        decode_serde(encoding, data)
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.q += el.q;
+            first.count += el.count;
+        }
+        Some(first)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct QuantilesGK {
+    q: quantiles::greenwald_khanna::Stream<ordered_float::NotNan<f64>>,
+    error: f64,
+    count: usize,
+    reinserted: usize,
+}
+impl QuantilesGK {
+    #[allow(dead_code)]
+    fn new(error: f64) -> Self {
+        let q = quantiles::greenwald_khanna::Stream::new(error);
+        QuantilesGK {
+            q,
+            error,
+            count: 0,
+            reinserted: 0,
+        }
+    }
+}
+impl Aggregate for QuantilesGK {
+    fn name(&self) -> &str {
+        if self.reinserted > 0 {
+            "QuantilesGK (reinsertion merge)"
+        } else {
+            "QuantilesGK"
+        }
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        **self.q.quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        // NaN has no rank, it is dropped. `Validated` counts them.
+        let Ok(value) = ordered_float::NotNan::new(value) else {
+            return;
+        };
+        self.q.insert(value);
+        self.count += 1;
+    }
+
+    /// The state of the library with serde, in bincode.
+    fn serialize(&mut self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(data)?)
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(self, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        decode_serde(encoding, data)
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for mut el in other {
+            let count = el.count;
+            merge_by_reinsertion(&mut first, &mut el, count);
+            first.reinserted += count;
+        }
+        Some(first)
+    }
+}
+
+/// Number of quantiles queried from a sketch in `merge_by_reinsertion`.
+const REINSERTION_POINTS: usize = 10_000;
+
+/// Fallback merge for sketches without merge support: `from` is replayed into `into` by
+/// querying `REINSERTION_POINTS` evenly spaced quantiles and inserting each of them with its share
+/// of `count`.
+///
+/// The cost is `count` inserts plus the queries, so about as much as ingesting the original
+/// values again, see the `MergeTime` column.
+///
+/// The merge adds error on top of the configured rank error `ε` of the sketches. The error of
+/// `from` is carried over into `into`, and every point stands for `count / REINSERTION_POINTS`
+/// ranks, which adds up to `1 / (2 * REINSERTION_POINTS)` when `count` exceeds the points. A
+/// merged sketch is within a rank error of `2ε + 1 / (2 * REINSERTION_POINTS)` instead of `ε`.
+fn merge_by_reinsertion<A: Aggregate>(into: &mut A, from: &mut A, count: usize) {
+    if count == 0 {
+        return;
+    }
+    let points = count.min(REINSERTION_POINTS);
+    let values: Vec<f64> = (0..points)
+        .map(|i| from.get_quantil((i as f64 + 0.5) / points as f64))
+        .collect();
+    for (i, value) in values.into_iter().enumerate() {
+        // Spread the remainder over the points, so exactly `count` values are inserted.
+        let repeat = count * (i + 1) / points - count * i / points;
+        for _ in 0..repeat {
+            into.insert(value);
+        }
+    }
+}
+
+/// Distinct values of a sketch with the number of ranks they cover.
+///
+/// Used to serialize sketches whose library has no serialization. The encoding is lossy: the
+/// values are what the sketch answers for its ranks, not its internal state. For a bucket based
+/// sketch they are the bucket values, inserting them again lands in the same buckets unless a
+/// value sits on a bucket boundary. The restored quantiles stay within the error bounds of the
+/// sketch, see `Aggregate::lossy_serialization`.
+#[derive(Serialize, Deserialize)]
+struct RankSteps {
+    /// The error the sketch was created with.
+    error: f64,
+    /// The total weight of the sketch, the number of values for an unweighted sketch.
+    weight: f64,
+    steps: Vec<(f64, u64)>,
+}
+
+impl RankSteps {
+    /// Queries the ranks `0..count` with galloping and binary search.
+    fn new<A: Aggregate>(aggregate: &mut A, error: f64, count: usize) -> Self {
+        let mut at_rank = |rank: usize| {
+            let q = if count == 1 {
+                0.0
+            } else {
+                rank as f64 / (count - 1) as f64
+            };
+            aggregate.get_quantil(q)
+        };
+        let mut steps = Vec::new();
+        let mut rank = 0;
+        while rank < count {
+            let value = at_rank(rank);
+            // `at_rank(low) == value`, `high` is the first rank known to have another value.
+            let mut low = rank;
+            let mut width = 1;
+            let mut high = loop {
+                let next = low + width;
+                if next >= count || at_rank(next) != value {
+                    break next.min(count);
+                }
+                low = next;
+                width *= 2;
+            };
+            while high - low > 1 {
+                let mid = (low + high) / 2;
+                if at_rank(mid) == value {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            steps.push((value, (high - rank) as u64));
+            rank = high;
+        }
+        Self {
+            error,
+            weight: count as f64,
+            steps,
+        }
+    }
+
+    /// For weighted sketches, the ranks are spread evenly over `weight`.
+    fn with_weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// The number of ranks, the number of values the sketch was built from.
+    fn ranks(&self) -> u64 {
+        self.steps.iter().map(|(_, count)| count).sum()
+    }
+
+    /// The weight of a step with `count` ranks.
+    fn step_weight(&self, count: u64) -> f64 {
+        count as f64 * self.weight / self.ranks() as f64
+    }
+
+    /// `Native` is the error, the weight, the number of steps and the steps as `(value, count)`
+    /// pairs. `Varint` stores the values delta encoded and the counts as varints.
+    fn encode(&self, encoding: Encoding) -> Option<Vec<u8>> {
+        let out = match encoding {
+            Encoding::Native => {
+                let mut out = Vec::with_capacity(20 + self.steps.len() * 16);
+                out.extend_from_slice(&self.error.to_le_bytes());
+                out.extend_from_slice(&self.weight.to_le_bytes());
+                out.extend_from_slice(&(self.steps.len() as u32).to_le_bytes());
+                for (value, count) in self.steps.iter() {
+                    out.extend_from_slice(&value.to_le_bytes());
+                    out.extend_from_slice(&count.to_le_bytes());
+                }
+                out
+            }
+            Encoding::Json | Encoding::Bincode => encode_serde(self, encoding).unwrap(),
+            Encoding::Varint => {
+                let mut out = self.error.to_le_bytes().to_vec();
+                out.extend_from_slice(&self.weight.to_le_bytes());
+                let values: Vec<f64> = self.steps.iter().map(|(value, _)| *value).collect();
+                encoding::put_sorted_f64s(&mut out, &values);
+                for (_, count) in self.steps.iter() {
+                    encoding::put_varint(&mut out, *count);
+                }
+                out
+            }
+            Encoding::Protobuf | Encoding::V2Deflate => return None,
+        };
+        Some(out)
+    }
+
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Native => {
+                let mut reader = encoding::Reader::new(data);
+                let error = reader.f64()?;
+                let weight = reader.f64()?;
+                let steps = (0..reader.u32()?)
+                    .map(|_| Ok((reader.f64()?, reader.u64()?)))
+                    .collect::<Result<_, encoding::DecodeError>>()?;
+                reader.finish()?;
+                Ok(Self {
+                    error,
+                    weight,
+                    steps,
+                })
+            }
+            Encoding::Json | Encoding::Bincode => decode_serde(encoding, data),
+            Encoding::Varint => {
+                let mut reader = encoding::Reader::new(data);
+                let error = reader.f64()?;
+                let weight = reader.f64()?;
+                let steps = reader
+                    .sorted_f64s()?
+                    .into_iter()
+                    .map(|value| Ok((value, reader.varint()?)))
+                    .collect::<Result<_, encoding::DecodeError>>()?;
+                reader.finish()?;
+                Ok(Self {
+                    error,
+                    weight,
+                    steps,
+                })
+            }
+            Encoding::Protobuf | Encoding::V2Deflate => Err("encoding is not supported".into()),
+        }
+    }
+
+    /// Creates the sketch with `new` from the error and inserts the steps again. A sketch with
+    /// weights gets every step once with its weight, the others get every rank as a value, so
+    /// restoring them costs as much as ingesting the original values.
+    fn restore<A: Aggregate>(self, new: impl Fn(f64) -> A) -> A {
+        let mut aggregate = new(self.error);
+        for (value, count) in self.steps.iter() {
+            if !aggregate.insert_weighted(*value, self.step_weight(*count)) {
+                for _ in 0..*count {
+                    aggregate.insert(*value);
+                }
+            }
+        }
+        aggregate.finalize();
+        aggregate
+    }
+}
+
+#[derive(Clone)]
+struct TDigest {
+    batch: Vec<f64>,
+    batch_size: usize,
+    t: tdigest::TDigest,
+}
+impl TDigest {
+    fn new(batch_size: usize, max_size: usize) -> Self {
+        let batch = Vec::new();
+        let t = tdigest::TDigest::new_with_size(max_size);
+        TDigest {
+            batch,
+            t,
+            batch_size,
+        }
+    }
+    fn apply_batch(&mut self) {
+        self.batch.sort_unstable_by(|a, b| a.total_cmp(b));
+
+        self.t = self.t.merge_sorted(&mut self.batch);
+        self.batch.clear();
+    }
+}
+impl Aggregate for TDigest {
+    fn name(&self) -> &str {
+        "TDigest"
+    }
+    fn finalize(&mut self) {
+        self.apply_batch();
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.t.estimate_quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        if self.batch.len() == self.batch_size {
+            self.apply_batch();
+        }
+        self.batch.push(value);
+    }
+    /// The batch size as `u64`, followed by the digest in bincode.
+    fn serialize(&mut self) -> Vec<u8> {
+        self.apply_batch();
+        let mut out = (self.batch_size as u64).to_le_bytes().to_vec();
+        bincode::serialize_into(&mut out, &self.t).unwrap();
+        out
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = encoding::Reader::new(data);
+        let batch_size = reader.u64()? as usize;
+        let t = bincode::deserialize(reader.rest())?;
+        Ok(Self {
+            batch: vec![],
+            t,
+            batch_size,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        self.apply_batch();
+        encode_serde(&(self.batch_size, &self.t), encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let (batch_size, t) = decode_serde(encoding, data)?;
+        Ok(Self {
+            batch: vec![],
+            t,
+            batch_size,
+        })
+    }
+
+    fn merge(other: Vec<Self>) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        let batch_size = other[0].batch_size;
+        let ts: Vec<tdigest::TDigest> = other.into_iter().map(|el| el.t).collect();
+        let t = tdigest::TDigest::merge_digests(ts);
+        Some(Self {
+            batch: vec![],
+            t,
+            batch_size,
+        })
+    }
+}
+
+struct ZWQuantile {
+    sum: UnboundEpsilonSummary<ordered_float::NotNan<f64>>,
+    epsilon: f64,
+    count: usize,
+    reinserted: usize,
+}
+impl ZWQuantile {
+    #[allow(dead_code)]
+    fn new(epsilon: f64) -> Self {
+        ZWQuantile {
+            sum: UnboundEpsilonSummary::new(epsilon),
+            epsilon,
+            count: 0,
+            reinserted: 0,
+        }
+    }
+}
+impl Aggregate for ZWQuantile {
+    fn name(&self) -> &str {
+        if self.reinserted > 0 {
+            "ZWQuantile (reinsertion merge)"
+        } else {
+            "ZWQuantile"
+        }
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.sum.query(q).into_inner()
+    }
+    fn insert(&mut self, value: f64) {
+        // NaN has no rank, it is dropped. `Validated` counts them.
+        let Ok(value) = ordered_float::NotNan::new(value) else {
+            return;
+        };
+        self.sum.update(value);
+        self.count += 1;
+    }
+
+    // zw-fast-quantile has no serialization, the summary is recovered with `RankSteps`.
+    fn serialize(&mut self) -> Vec<u8> {
+        self.encode(Encoding::Native).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::decode(Encoding::Native, data)
+    }
+    fn lossy_serialization(&self) -> bool {
+        true
+    }
+    fn encode(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        let (error, count) = (self.epsilon, self.count);
+        RankSteps::new(self, error, count).encode(encoding)
+    }
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(RankSteps::decode(encoding, data)?.restore(ZWQuantile::new))
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for mut el in other {
+            let count = el.count;
+            merge_by_reinsertion(&mut first, &mut el, count);
+            first.reinserted += count;
+        }
+        Some(first)
+    }
+}
+
+#[derive(Clone)]
+struct HDRHistogram {
+    histogram: Histogram<u64>,
+}
+impl HDRHistogram {
+    fn new(sigfig: u8) -> Self {
+        Self {
+            histogram: Histogram::new(sigfig).unwrap(),
+        }
+    }
+}
+impl Aggregate for HDRHistogram {
+    fn name(&self) -> &str {
+        "HDRHistogram"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.value_at_quantile(q) as f64
+    }
+    fn insert(&mut self, value: f64) {
+        // `as` saturates, NaN is recorded as 0 and +inf as `u64::MAX`, which is clamped to the
+        // range of the histogram if it can't grow that far.
+        let value = value as u64;
+        if self.histogram.record(value).is_err() {
+            self.histogram.saturating_record(value);
+        }
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        V2Serializer::new()
+            .serialize(&self.histogram, &mut out)
+            .unwrap();
+        out
+    }
+    fn deserialize(mut data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut histogram: Histogram<u64> = Deserializer::new().deserialize(&mut data)?;
+        histogram.auto(true);
+        Ok(Self { histogram })
+    }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        if encoding != Encoding::V2Deflate {
+            return None;
+        }
+        let mut out = Vec::new();
+        V2DeflateSerializer::new()
+            .serialize(&self.histogram, &mut out)
+            .unwrap();
+        Some(out)
+    }
+    // The deserializer detects the compression from the cookie.
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::V2Deflate => Self::deserialize(data),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Clamped
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(HeapSize::hdr_histogram(&self.histogram))
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.histogram += el.histogram;
+        }
+        Some(first)
+    }
+}
+
+/// Records floats by multiplying them with a scale factor.
+struct HDRScaledHistogram {
+    histogram: hdr_float::ScaledHistogram,
+}
+impl HDRScaledHistogram {
+    fn auto(sigfig: u8) -> Self {
+        Self {
+            histogram: hdr_float::ScaledHistogram::auto(sigfig),
+        }
+    }
+    fn with_scale(sigfig: u8, scale: f64) -> Self {
+        Self {
+            histogram: hdr_float::ScaledHistogram::with_scale(sigfig, scale),
+        }
+    }
+}
+impl Aggregate for HDRScaledHistogram {
+    fn name(&self) -> &str {
+        "HDRScaled"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        self.histogram.record(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.histogram.serialize().unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: hdr_float::ScaledHistogram::deserialize(data)?,
+        })
+    }
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Clamped
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.histogram.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        first
+            .histogram
+            .merge(other.into_iter().map(|el| el.histogram).collect())
+            .unwrap();
+        Some(first)
+    }
+}
+
+/// Auto-ranging float histogram, like `DoubleHistogram` of the Java implementation.
+struct HDRDoubleHistogram {
+    histogram: hdr_float::DoubleHistogram,
+}
+impl HDRDoubleHistogram {
+    fn new(dynamic_range_bits: u32, sigfig: u8) -> Self {
+        Self {
+            histogram: hdr_float::DoubleHistogram::new(dynamic_range_bits, sigfig),
+        }
+    }
+}
+impl Aggregate for HDRDoubleHistogram {
+    fn name(&self) -> &str {
+        "HDRDouble"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        self.histogram.record(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.histogram.serialize().unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: hdr_float::DoubleHistogram::deserialize(data)?,
+        })
+    }
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Clamped
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.histogram.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        first
+            .histogram
+            .merge(other.into_iter().map(|el| el.histogram).collect())
+            .unwrap();
+        Some(first)
+    }
+}
+
+#[derive(Clone)]
+struct DDSketch {
+    sketch: sketches_ddsketch::DDSketch,
+}
+
+impl DDSketch {
+    fn new() -> Self {
+        let c = sketches_ddsketch::Config::defaults();
+        let sketch = sketches_ddsketch::DDSketch::new(c);
+        Self { sketch }
+    }
+
+    fn with_alpha(alpha: f64) -> Self {
+        let defaults = sketches_ddsketch::Config::defaults();
+        let c =
+            sketches_ddsketch::Config::new(alpha, defaults.max_num_bins, defaults.min_possible());
+        let sketch = sketches_ddsketch::DDSketch::new(c);
+        Self { sketch }
+    }
+}
+
+impl Aggregate for DDSketch {
+    fn name(&self) -> &str {
+        "DDSketch"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.sketch.quantile(q).unwrap().unwrap()
+    }
+    fn insert(&mut self, value: f64) {
+        self.sketch.add(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        bincode::serialize(&self.sketch).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            sketch: bincode::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.sketch, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            sketch: decode_serde(encoding, data)?,
+        })
+    }
+    // The crate keeps its bins private, `ddsketch_state` reads them through its serde
+    // implementation.
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Protobuf)
+            .then(|| ddsketch_state::to_proto(&self.sketch).unwrap().encode())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Protobuf => Ok(Self {
+                sketch: ddsketch_state::from_proto(&ddsketch_proto::DDSketch::decode(data)?)?,
+            }),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(ddsketch_state::heap_size(&self.sketch).unwrap())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.sketch.merge(&el.sketch).unwrap();
+        }
+        Some(first)
+    }
+}
+
+use sketches_rust::store::UnboundedSizeDenseStore;
+use sketches_rust::store::{CollapsingHighestDenseStore, Store};
+use sketches_rust::{
+    index_mapping::{CubicallyInterpolatedMapping, IndexMapping, LogarithmicMapping},
+    store::CollapsingLowestDenseStore,
+};
+#[derive(Clone)]
+struct DDSketch2<I: IndexMapping, T: Store> {
+    sketch: sketches_rust::DDSketch<I, T>,
+    error: f64,
+    /// Number of inserted values.
+    count: usize,
+    /// Sum of the weights, equal to `count` without `insert_weighted`.
+    weight: f64,
+}
+
+impl DDSketch2<CubicallyInterpolatedMapping, UnboundedSizeDenseStore> {
+    fn unbounded(error: f64) -> Self {
+        Self {
+            sketch: sketches_rust::DDSketch::unbounded_dense(error).unwrap(),
+            error,
+            count: 0,
+            weight: 0.0,
+        }
+    }
+}
+
+impl DDSketch2<LogarithmicMapping, CollapsingLowestDenseStore> {
+    #[allow(unused)]
+    fn logarithmic_low(error: f64) -> Self {
+        Self {
+            sketch: sketches_rust::DDSketch::logarithmic_collapsing_lowest_dense(error, 2000)
+                .unwrap(),
+            error,
+            count: 0,
+            weight: 0.0,
+        }
+    }
+}
+
+impl DDSketch2<LogarithmicMapping, CollapsingHighestDenseStore> {
+    #[allow(unused)]
+    fn logarithmic_high(error: f64) -> Self {
+        Self {
+            sketch: sketches_rust::DDSketch::logarithmic_collapsing_highest_dense(error, 2000)
+                .unwrap(),
+            error,
+            count: 0,
+            weight: 0.0,
+        }
+    }
+}
+
+/// Constructor of a `DDSketch2` variant by its relative error, needed to deserialize it, and the
+/// interpolation of its index mapping in the protobuf format.
+trait DDSketch2Variant: Sized {
+    const INTERPOLATION: ddsketch_proto::Interpolation;
+    fn with_error(error: f64) -> Self;
+}
+impl DDSketch2Variant for DDSketch2<CubicallyInterpolatedMapping, UnboundedSizeDenseStore> {
+    const INTERPOLATION: ddsketch_proto::Interpolation = ddsketch_proto::Interpolation::Cubic;
+    fn with_error(error: f64) -> Self {
+        Self::unbounded(error)
+    }
 }
-impl Aggregate for QuantilesCKMS {
+impl DDSketch2Variant for DDSketch2<LogarithmicMapping, CollapsingLowestDenseStore> {
+    const INTERPOLATION: ddsketch_proto::Interpolation = ddsketch_proto::Interpolation::None;
+    fn with_error(error: f64) -> Self {
+        Self::logarithmic_low(error)
+    }
+}
+impl DDSketch2Variant for DDSketch2<LogarithmicMapping, CollapsingHighestDenseStore> {
+    const INTERPOLATION: ddsketch_proto::Interpolation = ddsketch_proto::Interpolation::None;
+    fn with_error(error: f64) -> Self {
+        Self::logarithmic_high(error)
+    }
+}
+
+impl<I: IndexMapping, T: Store> Aggregate for DDSketch2<I, T>
+where
+    Self: DDSketch2Variant,
+{
     fn name(&self) -> &str {
-        "QuantilesCKMS"
+        "DDSketch2"
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.q.query(q).unwrap().1
+        assert!((0f64..=1f64).contains(&q));
+        self.sketch.get_value_at_quantile(q).unwrap()
     }
     fn insert(&mut self, value: f64) {
-        self.q.insert(value);
+        self.sketch.accept(value);
+        self.count += 1;
+        self.weight += 1.0;
+    }
+    fn insert_weighted(&mut self, value: f64, weight: f64) -> bool {
+        self.sketch.accept_with_count(value, weight).unwrap();
+        self.count += 1;
+        self.weight += weight;
+        true
+    }
+
+    // sketches-rust has no serialization, the buckets are recovered with `RankSteps` over the
+    // `count` values and carry their share of the total weight. For `Protobuf` the bin indices
+    // are computed again from the bin values with the same mapping.
+    fn serialize(&mut self) -> Vec<u8> {
+        self.encode(Encoding::Native).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::decode(Encoding::Native, data)
+    }
+    fn lossy_serialization(&self) -> bool {
+        true
+    }
+    fn encode(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        let (error, count, weight) = (self.error, self.count, self.weight);
+        let steps = RankSteps::new(self, error, count).with_weight(weight);
+        if encoding != Encoding::Protobuf {
+            return steps.encode(encoding);
+        }
+        let mapping = ddsketch_proto::LogLikeMapping::new(error, Self::INTERPOLATION);
+        let mut proto = ddsketch_proto::DDSketch::new(mapping.to_proto());
+        for (value, count) in steps.steps.iter() {
+            let weight = steps.step_weight(*count);
+            if *value > 0.0 {
+                proto.positive.add(mapping.index(*value), weight);
+            } else if *value < 0.0 {
+                proto.negative.add(mapping.index(-*value), weight);
+            } else {
+                proto.zero_count += weight;
+            }
+        }
+        Some(proto.encode())
+    }
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if encoding != Encoding::Protobuf {
+            let steps = RankSteps::decode(encoding, data)?;
+            let (count, weight) = (steps.ranks() as usize, steps.weight);
+            let mut sketch = steps.restore(Self::with_error);
+            sketch.count = count;
+            sketch.weight = weight;
+            return Ok(sketch);
+        }
+        let proto = ddsketch_proto::DDSketch::decode(data)?;
+        // sketches-rust has no index offset. An integer offset only renumbers the bins, the value
+        // of a bin lands in the same bin without offset; other offsets straddle two of its bins.
+        if proto.mapping.interpolation != Self::INTERPOLATION
+            || proto.mapping.index_offset.fract() != 0.0
+        {
+            return Err("the index mapping does not match the sketch".into());
+        }
+        let mapping = ddsketch_proto::LogLikeMapping::from_proto(&proto.mapping)?;
+        let bin =
+            |sign: f64| move |(index, count): (i32, f64)| (sign * mapping.value(index), count);
+        let bins: Vec<(f64, f64)> = proto
+            .negative
+            .bins()
+            .map(bin(-1.0))
+            .chain(std::iter::once((0.0, proto.zero_count)))
+            .chain(proto.positive.bins().map(bin(1.0)))
+            .filter(|(_, count)| *count > 0.0)
+            .collect();
+        let mut sketch = Self::with_error(mapping.relative_accuracy());
+        for (value, count) in bins {
+            sketch.insert_weighted(value, count);
+        }
+        // The message has no number of values, the weights are counts for unweighted sketches.
+        sketch.count = (sketch.weight.round() as usize).max(sketch.count);
+        Ok(sketch)
     }
     fn merge(mut other: Vec<Self>) -> Option<Self> {
         let mut first = other.pop().unwrap();
-        for el in other {
-            first.q += el.q;
+        for el in other.iter_mut() {
+            first.sketch.merge_with(&mut el.sketch).unwrap();
+            first.count += el.count;
+            first.weight += el.weight;
         }
         Some(first)
     }
 }
 
-struct QuantilesGK {
-    q: quantiles::greenwald_khanna::Stream<ordered_float::NotNan<f64>>,
+/// Relative error of `quantogram::Quantogram::new`, the default configuration of the library.
+const QUANTOGRAM_ERROR: f64 = 0.01;
+
+struct Quantogram {
+    quantogram: quantogram::Quantogram,
+    count: usize,
 }
-impl QuantilesGK {
-    #[allow(dead_code)]
-    fn new(error: f64) -> Self {
-        let q = quantiles::greenwald_khanna::Stream::new(error);
-        QuantilesGK { q }
+
+impl Quantogram {
+    fn new() -> Self {
+        Self {
+            quantogram: quantogram::Quantogram::new(),
+            count: 0,
+        }
     }
 }
-impl Aggregate for QuantilesGK {
+impl Aggregate for Quantogram {
     fn name(&self) -> &str {
-        "QuantilesGK"
+        "Quantogram"
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        **self.q.quantile(q)
+        assert!((0f64..=1f64).contains(&q));
+        self.quantogram.quantile(q).unwrap()
     }
     fn insert(&mut self, value: f64) {
-        let value = unsafe { ordered_float::NotNan::new_unchecked(value) };
-        self.q.insert(value);
+        self.quantogram.add(value);
+        self.count += 1;
+    }
+
+    // quantogram has no serialization, the bins are recovered with `RankSteps`. Its error is
+    // not configurable here, `QUANTOGRAM_ERROR` is stored for reference.
+    fn serialize(&mut self) -> Vec<u8> {
+        self.encode(Encoding::Native).unwrap()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::decode(Encoding::Native, data)
+    }
+    fn lossy_serialization(&self) -> bool {
+        true
+    }
+    fn encode(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        let (error, count) = (QUANTOGRAM_ERROR, self.count);
+        RankSteps::new(self, error, count).encode(encoding)
+    }
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(RankSteps::decode(encoding, data)?.restore(|_| Quantogram::new()))
     }
 }
 
-struct TDigest {
-    batch: Vec<f64>,
-    batch_size: usize,
-    t: tdigest::TDigest,
+#[derive(Clone)]
+struct PromNativeHistogram {
+    histogram: native_histogram::NativeHistogram,
 }
-impl TDigest {
-    fn new(batch_size: usize, max_size: usize) -> Self {
-        let batch = Vec::new();
-        let t = tdigest::TDigest::new_with_size(max_size);
-        TDigest {
-            batch,
-            t,
-            batch_size,
+
+impl PromNativeHistogram {
+    fn new(schema: i32, zero_threshold: f64) -> Self {
+        Self {
+            histogram: native_histogram::NativeHistogram::new(schema, zero_threshold),
         }
     }
-    fn apply_batch(&mut self) {
-        self.batch.sort_unstable_by(|a, b| a.total_cmp(b));
+    fn with_max_buckets(schema: i32, zero_threshold: f64, max_buckets: usize) -> Self {
+        Self {
+            histogram: native_histogram::NativeHistogram::new(schema, zero_threshold)
+                .with_max_buckets(max_buckets),
+        }
+    }
+}
+impl Aggregate for PromNativeHistogram {
+    fn name(&self) -> &str {
+        "PromNative"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        self.histogram.observe(value)
+    }
 
-        self.t = self.t.merge_sorted(&mut self.batch);
-        self.batch.clear();
+    fn serialize(&mut self) -> Vec<u8> {
+        self.histogram.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: native_histogram::NativeHistogram::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.histogram, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: decode_serde(encoding, data)?,
+        })
+    }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Varint).then(|| self.histogram.serialize_varint())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Varint => Ok(Self {
+                histogram: native_histogram::NativeHistogram::deserialize_varint(data)?,
+            }),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.histogram.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.histogram.merge(&el.histogram);
+        }
+        Some(first)
     }
 }
-impl Aggregate for TDigest {
+
+struct ClassicHistogram {
+    histogram: classic_histogram::ClassicHistogram,
+}
+
+impl ClassicHistogram {
+    fn new(bounds: Vec<f64>) -> Self {
+        Self {
+            histogram: classic_histogram::ClassicHistogram::new(bounds),
+        }
+    }
+    fn linear(start: f64, width: f64, count: usize) -> Self {
+        Self {
+            histogram: classic_histogram::ClassicHistogram::linear(start, width, count),
+        }
+    }
+    fn exponential(start: f64, factor: f64, count: usize) -> Self {
+        Self {
+            histogram: classic_histogram::ClassicHistogram::exponential(start, factor, count),
+        }
+    }
+}
+impl Aggregate for ClassicHistogram {
     fn name(&self) -> &str {
-        "TDigest"
+        "ClassicHistogram"
     }
-    fn finalize(&mut self) {
-        self.apply_batch();
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.quantile(q)
+    }
+    fn insert(&mut self, value: f64) {
+        self.histogram.observe(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.histogram.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: classic_histogram::ClassicHistogram::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.histogram, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: decode_serde(encoding, data)?,
+        })
+    }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Varint).then(|| self.histogram.serialize_varint())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Varint => Ok(Self {
+                histogram: classic_histogram::ClassicHistogram::deserialize_varint(data)?,
+            }),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.histogram.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.histogram.merge(&el.histogram).unwrap();
+        }
+        Some(first)
+    }
+}
+
+#[derive(Clone)]
+struct Circllhist {
+    histogram: circllhist::Circllhist,
+}
+
+impl Circllhist {
+    fn new() -> Self {
+        Self {
+            histogram: circllhist::Circllhist::new(),
+        }
+    }
+}
+impl Aggregate for Circllhist {
+    fn name(&self) -> &str {
+        "Circllhist"
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.t.estimate_quantile(q)
+        assert!((0f64..=1f64).contains(&q));
+        self.histogram.quantile(q).unwrap()
     }
     fn insert(&mut self, value: f64) {
-        if self.batch.len() == self.batch_size {
-            self.apply_batch();
+        self.histogram.insert(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.histogram.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: circllhist::Circllhist::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.histogram, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            histogram: decode_serde(encoding, data)?,
+        })
+    }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Varint).then(|| self.histogram.serialize_varint())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Varint => Ok(Self {
+                histogram: circllhist::Circllhist::deserialize_varint(data)?,
+            }),
+            _ => Err("encoding is not supported".into()),
         }
-        self.batch.push(value);
     }
-    fn serialize_size(&self) -> usize {
-        //let encoded: Vec<u8> = bincode::serialize(&self.t).unwrap();
-        //encoded.len()
-        serde_json::to_string(&self.t).unwrap().len()
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.histogram.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.histogram.merge(&el.histogram);
+        }
+        Some(first)
     }
+}
 
-    fn merge(other: Vec<Self>) -> Option<Self>
-    where
-        Self: Sized,
-    {
-        let batch_size = other[0].batch_size;
-        let ts: Vec<tdigest::TDigest> = other.into_iter().map(|el| el.t).collect();
-        let t = tdigest::TDigest::merge_digests(ts);
-        Some(Self {
-            batch: vec![],
-            t,
-            batch_size,
+struct QDigest {
+    digest: qdigest::QDigest,
+}
+
+impl QDigest {
+    fn new(compression: u64, universe_bits: u32) -> Self {
+        Self {
+            digest: qdigest::QDigest::new(compression, universe_bits),
+        }
+    }
+}
+impl Aggregate for QDigest {
+    fn name(&self) -> &str {
+        "QDigest"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.digest.quantile(q).unwrap() as f64
+    }
+    fn insert(&mut self, value: f64) {
+        // Supports only integers, same as HDRHistogram.
+        self.digest.insert(value as u64)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.digest.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            digest: qdigest::QDigest::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.digest, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            digest: decode_serde(encoding, data)?,
         })
     }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Varint).then(|| self.digest.serialize_varint())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Varint => Ok(Self {
+                digest: qdigest::QDigest::deserialize_varint(data)?,
+            }),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Clamped
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.digest.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        for el in other {
+            first.digest.merge(&el.digest).unwrap();
+        }
+        Some(first)
+    }
 }
 
-struct ZWQuantile {
-    sum: UnboundEpsilonSummary<ordered_float::NotNan<f64>>,
+struct Reservoir {
+    reservoir: reservoir::Reservoir,
 }
-impl ZWQuantile {
-    #[allow(dead_code)]
-    fn new(epsilon: f64) -> Self {
-        ZWQuantile {
-            sum: UnboundEpsilonSummary::new(epsilon),
+
+impl Reservoir {
+    fn new(size: usize) -> Self {
+        Self {
+            reservoir: reservoir::Reservoir::new(size),
         }
     }
 }
-impl Aggregate for ZWQuantile {
+impl Aggregate for Reservoir {
+    fn name(&self) -> &str {
+        "Reservoir"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.reservoir.quantile(q).unwrap()
+    }
+    fn insert(&mut self, value: f64) {
+        self.reservoir.insert(value)
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.reservoir.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            reservoir: reservoir::Reservoir::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.reservoir, encoding)
+    }
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            reservoir: decode_serde(encoding, data)?,
+        })
+    }
+    fn encode_format(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        (encoding == Encoding::Varint).then(|| self.reservoir.serialize_varint())
+    }
+    fn decode_format(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match encoding {
+            Encoding::Varint => Ok(Self {
+                reservoir: reservoir::Reservoir::deserialize_varint(data)?,
+            }),
+            _ => Err("encoding is not supported".into()),
+        }
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.reservoir.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        let others: Vec<_> = other.into_iter().map(|el| el.reservoir).collect();
+        first.reservoir.merge(&others);
+        Some(first)
+    }
+}
+
+struct WeightedReservoir {
+    reservoir: reservoir::WeightedReservoir,
+}
+
+impl WeightedReservoir {
+    fn new(size: usize) -> Self {
+        Self {
+            reservoir: reservoir::WeightedReservoir::new(size),
+        }
+    }
+}
+impl Aggregate for WeightedReservoir {
     fn name(&self) -> &str {
-        "ZWQuantile"
+        "WeightedReservoir"
+    }
+    fn get_quantil(&mut self, q: f64) -> f64 {
+        assert!((0f64..=1f64).contains(&q));
+        self.reservoir.quantile(q).unwrap()
+    }
+    fn insert(&mut self, value: f64) {
+        self.reservoir.insert_weighted(value, 1.0)
+    }
+    fn insert_weighted(&mut self, value: f64, weight: f64) -> bool {
+        self.reservoir.insert_weighted(value, weight);
+        true
+    }
+
+    fn serialize(&mut self) -> Vec<u8> {
+        self.reservoir.serialize()
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            reservoir: reservoir::WeightedReservoir::deserialize(data)?,
+        })
+    }
+    fn encode_state(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        encode_serde(&self.reservoir, encoding)
     }
-    fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.sum.query(q).into_inner()
+    fn decode_state(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            reservoir: decode_serde(encoding, data)?,
+        })
     }
-    fn insert(&mut self, value: f64) {
-        let value = unsafe { ordered_float::NotNan::new_unchecked(value) };
-        self.sum.update(value)
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.reservoir.heap_size())
+    }
+    fn merge(mut other: Vec<Self>) -> Option<Self> {
+        let mut first = other.pop().unwrap();
+        first
+            .reservoir
+            .merge(other.into_iter().map(|el| el.reservoir).collect());
+        Some(first)
     }
 }
 
-struct HDRHistogram {
-    histogram: Histogram<u64>,
+/// Routes negative values to a second instance, which records them negated. Makes backends
+/// without support for negative values usable on mixed-sign data.
+struct Mirrored<A: Aggregate> {
+    name: String,
+    positive: A,
+    negative: A,
+    positive_count: u64,
+    negative_count: u64,
 }
-impl HDRHistogram {
-    fn new(sigfig: u8) -> Self {
+
+impl<A: Aggregate> Mirrored<A> {
+    fn new(aggregate: impl Fn() -> A) -> Self {
+        let positive = aggregate();
         Self {
-            histogram: Histogram::new(sigfig).unwrap(),
+            name: format!("{} (mirrored)", positive.name()),
+            positive,
+            negative: aggregate(),
+            positive_count: 0,
+            negative_count: 0,
         }
     }
 }
-impl Aggregate for HDRHistogram {
+impl<A: Aggregate> Aggregate for Mirrored<A> {
     fn name(&self) -> &str {
-        "HDRHistogram"
+        &self.name
+    }
+    fn finalize(&mut self) {
+        self.positive.finalize();
+        self.negative.finalize();
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.histogram.value_at_quantile(q) as f64
+        assert!((0f64..=1f64).contains(&q));
+        if self.negative_count == 0 {
+            return self.positive.get_quantil(q);
+        }
+        let rank = q * (self.positive_count + self.negative_count) as f64;
+        let negative_count = self.negative_count as f64;
+        if rank < negative_count || self.positive_count == 0 {
+            // The negative side is stored negated, so its order is reversed.
+            -self
+                .negative
+                .get_quantil(1.0 - (rank / negative_count).min(1.0))
+        } else {
+            let q = (rank - negative_count) / self.positive_count as f64;
+            self.positive.get_quantil(q.min(1.0))
+        }
     }
     fn insert(&mut self, value: f64) {
-        self.histogram.record(value as u64).unwrap()
+        if value < 0.0 {
+            self.negative_count += 1;
+            self.negative.insert(-value);
+        } else {
+            self.positive_count += 1;
+            self.positive.insert(value);
+        }
     }
 
-    fn serialize_size(&self) -> usize {
-        let mut vec = Vec::new();
-        V2Serializer::new()
-            .serialize(&self.histogram, &mut vec)
-            .unwrap();
-
-        vec.len()
+    fn serialize(&mut self) -> Vec<u8> {
+        self.encode(Encoding::Native).unwrap()
     }
-    fn merge(mut other: Vec<Self>) -> Option<Self> {
-        let mut first = other.pop().unwrap();
-        for el in other {
-            first.histogram += el.histogram;
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::decode(Encoding::Native, data)
+    }
+    /// Both counts, the length of the positive side and both sides in `encoding`.
+    fn encode(&mut self, encoding: Encoding) -> Option<Vec<u8>> {
+        let positive = self.positive.encode(encoding)?;
+        let negative = self.negative.encode(encoding)?;
+        let mut out = Vec::with_capacity(24 + positive.len() + negative.len());
+        out.extend_from_slice(&self.positive_count.to_le_bytes());
+        out.extend_from_slice(&self.negative_count.to_le_bytes());
+        out.extend_from_slice(&(positive.len() as u64).to_le_bytes());
+        out.extend_from_slice(&positive);
+        out.extend_from_slice(&negative);
+        Some(out)
+    }
+    fn decode(encoding: Encoding, data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = encoding::Reader::new(data);
+        let positive_count = reader.u64()?;
+        let negative_count = reader.u64()?;
+        let positive_len = reader.u64()? as usize;
+        let positive = A::decode(encoding, reader.bytes(positive_len)?)?;
+        let negative = A::decode(encoding, reader.rest())?;
+        Ok(Self {
+            name: format!("{} (mirrored)", positive.name()),
+            positive,
+            negative,
+            positive_count,
+            negative_count,
+        })
+    }
+    fn lossy_serialization(&self) -> bool {
+        self.positive.lossy_serialization()
+    }
+    fn negative_values(&self) -> NegativeValues {
+        NegativeValues::Mirrored
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        Some(self.positive.heap_size()? + self.negative.heap_size()?)
+    }
+    fn merge(other: Vec<Self>) -> Option<Self> {
+        let name = other[0].name.clone();
+        let positive_count = other.iter().map(|el| el.positive_count).sum();
+        let negative_count = other.iter().map(|el| el.negative_count).sum();
+        let (positives, negatives): (Vec<A>, Vec<A>) = other
+            .into_iter()
+            .map(|el| (el.positive, el.negative))
+            .unzip();
+        Some(Self {
+            name,
+            positive: A::merge(positives)?,
+            negative: A::merge(negatives)?,
+            positive_count,
+            negative_count,
+        })
+    }
+}
+
+/// What `Validated` does with values that are NaN or infinite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InvalidValuePolicy {
+    /// `Validated::try_insert` returns an error, `insert` panics.
+    Reject,
+    /// The value is dropped and counted.
+    Skip,
+    /// Infinite and out of range values are clamped to `[min, max]`. NaN is dropped and counted.
+    Clamp { min: f64, max: f64 },
+}
+
+impl std::fmt::Display for InvalidValuePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidValuePolicy::Reject => write!(f, "Reject"),
+            InvalidValuePolicy::Skip => write!(f, "Skip"),
+            InvalidValuePolicy::Clamp { min, max } => write!(f, "Clamp [{:e}, {:e}]", min, max),
         }
-        Some(first)
     }
 }
 
-struct DDSketch {
-    sketch: sketches_ddsketch::DDSketch,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InvalidValue {
+    NaN,
+    Infinity,
+    NegInfinity,
 }
 
-impl DDSketch {
-    fn new() -> Self {
-        let c = sketches_ddsketch::Config::defaults();
-        let sketch = sketches_ddsketch::DDSketch::new(c);
-        Self { sketch }
+impl std::fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            InvalidValue::NaN => "NaN",
+            InvalidValue::Infinity => "inf",
+            InvalidValue::NegInfinity => "-inf",
+        };
+        write!(f, "rejected {}", text)
     }
 }
-impl Aggregate for DDSketch {
+
+/// Checks every value before it is passed to the wrapped aggregate, so backends never see NaN
+/// or infinite values.
+struct Validated<A: Aggregate> {
+    aggregate: A,
+    policy: InvalidValuePolicy,
+    skipped: u64,
+    clamped: u64,
+}
+
+impl<A: Aggregate> Validated<A> {
+    fn new(aggregate: A, policy: InvalidValuePolicy) -> Self {
+        Self {
+            aggregate,
+            policy,
+            skipped: 0,
+            clamped: 0,
+        }
+    }
+
+    fn try_insert(&mut self, value: f64) -> Result<(), InvalidValue> {
+        let invalid = if value.is_nan() {
+            Some(InvalidValue::NaN)
+        } else if value == f64::INFINITY {
+            Some(InvalidValue::Infinity)
+        } else if value == f64::NEG_INFINITY {
+            Some(InvalidValue::NegInfinity)
+        } else {
+            None
+        };
+        match (self.policy, invalid) {
+            (InvalidValuePolicy::Reject, Some(invalid)) => return Err(invalid),
+            (InvalidValuePolicy::Skip, Some(_))
+            | (InvalidValuePolicy::Clamp { .. }, Some(InvalidValue::NaN)) => {
+                self.skipped += 1;
+            }
+            (InvalidValuePolicy::Clamp { min, max }, _) if value < min || value > max => {
+                self.clamped += 1;
+                self.aggregate.insert(value.clamp(min, max));
+            }
+            _ => self.aggregate.insert(value),
+        }
+        Ok(())
+    }
+}
+impl<A: Aggregate> Aggregate for Validated<A> {
     fn name(&self) -> &str {
-        "DDSketch"
+        self.aggregate.name()
+    }
+    fn finalize(&mut self) {
+        self.aggregate.finalize();
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.sketch.quantile(q).unwrap().unwrap()
+        self.aggregate.get_quantil(q)
     }
     fn insert(&mut self, value: f64) {
-        self.sketch.add(value)
+        if let Err(invalid) = self.try_insert(value) {
+            panic!("{}", invalid);
+        }
     }
 
-    fn serialize_size(&self) -> usize {
-        serde_json::to_string(&self.sketch).unwrap().len()
-    }
-    fn merge(mut other: Vec<Self>) -> Option<Self> {
-        let mut first = other.pop().unwrap();
-        for el in other {
-            first.sketch.merge(&el.sketch).unwrap();
+    /// The policy, the counters and the wrapped aggregate.
+    fn serialize(&mut self) -> Vec<u8> {
+        let (tag, min, max) = match self.policy {
+            InvalidValuePolicy::Reject => (0u8, 0.0, 0.0),
+            InvalidValuePolicy::Skip => (1, 0.0, 0.0),
+            InvalidValuePolicy::Clamp { min, max } => (2, min, max),
+        };
+        let mut out = vec![tag];
+        for value in [min, max] {
+            out.extend_from_slice(&value.to_le_bytes());
         }
-        Some(first)
+        for counter in [self.skipped, self.clamped] {
+            out.extend_from_slice(&counter.to_le_bytes());
+        }
+        out.extend_from_slice(&self.aggregate.serialize());
+        out
+    }
+    fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = encoding::Reader::new(data);
+        let tag = reader.u8()?;
+        let (min, max) = (reader.f64()?, reader.f64()?);
+        let policy = match tag {
+            0 => InvalidValuePolicy::Reject,
+            1 => InvalidValuePolicy::Skip,
+            2 => InvalidValuePolicy::Clamp { min, max },
+            _ => return Err(encoding::DecodeError::Invalid("policy").into()),
+        };
+        let skipped = reader.u64()?;
+        let clamped = reader.u64()?;
+        Ok(Self {
+            aggregate: A::deserialize(reader.rest())?,
+            policy,
+            skipped,
+            clamped,
+        })
+    }
+    fn lossy_serialization(&self) -> bool {
+        self.aggregate.lossy_serialization()
+    }
+    fn negative_values(&self) -> NegativeValues {
+        self.aggregate.negative_values()
+    }
+    fn heap_size(&self) -> Option<HeapSize> {
+        self.aggregate.heap_size()
+    }
+    fn merge(other: Vec<Self>) -> Option<Self> {
+        let policy = other[0].policy;
+        let skipped = other.iter().map(|el| el.skipped).sum();
+        let clamped = other.iter().map(|el| el.clamped).sum();
+        let aggregate = A::merge(other.into_iter().map(|el| el.aggregate).collect())?;
+        Some(Self {
+            aggregate,
+            policy,
+            skipped,
+            clamped,
+        })
     }
 }
 
-use sketches_rust::store::UnboundedSizeDenseStore;
-use sketches_rust::store::{CollapsingHighestDenseStore, Store};
-use sketches_rust::{
-    index_mapping::{CubicallyInterpolatedMapping, IndexMapping, LogarithmicMapping},
-    store::CollapsingLowestDenseStore,
-};
-struct DDSketch2<I: IndexMapping, T: Store> {
-    sketch: sketches_rust::DDSketch<I, T>,
+/// The window a `Windowed` aggregate answers for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WindowKind {
+    /// The last complete window, windows don't overlap.
+    Tumbling,
+    /// The last `slots` sub-windows including the current, incomplete one.
+    Sliding { slots: usize },
 }
 
-impl DDSketch2<CubicallyInterpolatedMapping, UnboundedSizeDenseStore> {
-    fn unbounded(error: f64) -> Self {
-        Self {
-            sketch: sketches_rust::DDSketch::unbounded_dense(error).unwrap(),
+impl std::fmt::Display for WindowKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowKind::Tumbling => write!(f, "tumbling"),
+            WindowKind::Sliding { slots } => write!(f, "sliding ({} slots)", slots),
         }
     }
 }
 
-impl DDSketch2<LogarithmicMapping, CollapsingLowestDenseStore> {
-    #[allow(unused)]
-    fn logarithmic_low(error: f64) -> Self {
+/// Quantiles over a time window, kept as a ring of sub-aggregates. The time is advanced with
+/// `advance`, values are inserted into the current sub-aggregate.
+///
+/// A sliding window merges clones of its sub-aggregates on the first query after a change, so
+/// the backend has to be `Clone` and support `merge`. A window without values has no answer, in
+/// particular a tumbling window before its first window is complete.
+struct Windowed<A: Aggregate + Clone, F: Fn() -> A> {
+    aggregate: F,
+    kind: WindowKind,
+    slot_length: std::time::Duration,
+    /// Start of the current slot, which is the last one.
+    slot_start: std::time::Duration,
+    /// The sub-aggregates with the number of values inserted into them.
+    slots: std::collections::VecDeque<(A, u64)>,
+    merged: Option<A>,
+}
+
+impl<A: Aggregate + Clone, F: Fn() -> A> Windowed<A, F> {
+    /// `window` is the length of the whole window, a sliding window splits it into its slots.
+    fn new(kind: WindowKind, window: std::time::Duration, aggregate: F) -> Self {
+        let slot_length = match kind {
+            WindowKind::Tumbling => window,
+            WindowKind::Sliding { slots } => {
+                assert!(slots > 0, "a sliding window needs at least one slot");
+                window / slots as u32
+            }
+        };
         Self {
-            sketch: sketches_rust::DDSketch::logarithmic_collapsing_lowest_dense(error, 2000)
-                .unwrap(),
+            slots: std::collections::VecDeque::from([(aggregate(), 0)]),
+            aggregate,
+            kind,
+            slot_length,
+            slot_start: std::time::Duration::ZERO,
+            merged: None,
         }
     }
-}
 
-impl DDSketch2<LogarithmicMapping, CollapsingHighestDenseStore> {
-    #[allow(unused)]
-    fn logarithmic_high(error: f64) -> Self {
-        Self {
-            sketch: sketches_rust::DDSketch::logarithmic_collapsing_highest_dense(error, 2000)
-                .unwrap(),
+    /// A tumbling window keeps the previous window next to the current one.
+    fn capacity(&self) -> usize {
+        match self.kind {
+            WindowKind::Tumbling => 2,
+            WindowKind::Sliding { slots } => slots,
         }
     }
-}
 
-impl<I: IndexMapping, T: Store> Aggregate for DDSketch2<I, T> {
+    /// Rotates the slots until `now` falls into the current one.
+    fn advance(&mut self, now: std::time::Duration) {
+        if now < self.slot_start + self.slot_length {
+            return;
+        }
+        let elapsed = (now - self.slot_start).as_nanos();
+        let slot_length = self.slot_length.as_nanos();
+        // Slots that ended before the window are not created.
+        let rotations = (elapsed / slot_length).min(self.capacity() as u128) as usize;
+        for _ in 0..rotations {
+            if self.slots.len() == self.capacity() {
+                self.slots.pop_front();
+            }
+            if let Some((slot, _)) = self.slots.back_mut() {
+                slot.finalize();
+            }
+            self.slots.push_back(((self.aggregate)(), 0));
+        }
+        // The new slot starts a remainder, shorter than a slot, before `now`.
+        let remainder = elapsed % slot_length;
+        self.slot_start = now
+            - std::time::Duration::new(
+                (remainder / 1_000_000_000) as u64,
+                (remainder % 1_000_000_000) as u32,
+            );
+        self.merged = None;
+    }
+
+    /// The aggregate of the window, `None` if the window has no values, e.g. for a tumbling
+    /// window before its first rotation, and for a sliding window over a backend without `merge`.
+    fn window(&mut self) -> Option<&mut A> {
+        match self.kind {
+            WindowKind::Tumbling if self.slots.len() < 2 || self.slots[0].1 == 0 => None,
+            WindowKind::Tumbling => self.slots.front_mut().map(|(slot, _)| slot),
+            WindowKind::Sliding { .. } => {
+                if self.merged.is_none() {
+                    let slots: Vec<A> = self
+                        .slots
+                        .iter()
+                        .filter(|(_, count)| *count > 0)
+                        .map(|(slot, _)| slot.clone())
+                        .collect();
+                    if slots.is_empty() {
+                        return None;
+                    }
+                    self.merged = A::merge(slots);
+                }
+                self.merged.as_mut()
+            }
+        }
+    }
+}
+impl<A: Aggregate + Clone, F: Fn() -> A> Aggregate for Windowed<A, F> {
     fn name(&self) -> &str {
-        "DDSketch2"
+        self.slots[0].0.name()
     }
+    fn finalize(&mut self) {
+        if let Some(window) = self.window() {
+            window.finalize();
+        }
+    }
+    /// NaN without a window, see `window`.
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.sketch.get_value_at_quantile(q).unwrap()
+        self.window()
+            .map_or(f64::NAN, |window| window.get_quantil(q))
     }
     fn insert(&mut self, value: f64) {
-        self.sketch.accept(value)
-    }
-
-    fn serialize_size(&self) -> usize {
-        0
-    }
-    fn merge(mut other: Vec<Self>) -> Option<Self> {
-        let mut first = other.pop().unwrap();
-        for el in other.iter_mut() {
-            first.sketch.merge_with(&mut el.sketch).unwrap();
+        let (slot, count) = self.slots.back_mut().unwrap();
+        slot.insert(value);
+        *count += 1;
+        if matches!(self.kind, WindowKind::Sliding { .. }) {
+            self.merged = None;
         }
-        Some(first)
+    }
+    fn negative_values(&self) -> NegativeValues {
+        self.slots[0].0.negative_values()
     }
 }
 
-struct Quantogram {
-    quantogram: quantogram::Quantogram,
+/// Forward decay: a value inserted at time `t` gets the weight `exp(lambda * t)`, relative to a
+/// landmark at time 0, so recent values count more. As all weights grow at the same rate,
+/// quantiles only depend on their ratios and nothing has to be decayed on insert. Needs a backend
+/// with `insert_weighted`.
+///
+/// The weights overflow after about 700 / `lambda` seconds, e.g. after 16 hours with a half-life
+/// of one minute. Longer runs need a new landmark, i.e. a new aggregate.
+struct Decayed<A: Aggregate> {
+    name: String,
+    aggregate: A,
+    lambda: f64,
+    now: std::time::Duration,
 }
 
-impl Quantogram {
-    fn new() -> Self {
+impl<A: Aggregate> Decayed<A> {
+    /// Without a half-life all values have the same weight.
+    fn new(aggregate: A, half_life: Option<std::time::Duration>) -> Self {
+        let (name, lambda) = match half_life {
+            Some(half_life) => (
+                format!("{} (half-life {}s)", aggregate.name(), half_life.as_secs()),
+                std::f64::consts::LN_2 / half_life.as_secs_f64(),
+            ),
+            None => (format!("{} (no decay)", aggregate.name()), 0.0),
+        };
         Self {
-            quantogram: quantogram::Quantogram::new(),
+            name,
+            aggregate,
+            lambda,
+            now: std::time::Duration::ZERO,
         }
     }
+
+    fn advance(&mut self, now: std::time::Duration) {
+        self.now = now;
+    }
+
+    fn weight(&self) -> f64 {
+        let weight = (self.lambda * self.now.as_secs_f64()).exp();
+        assert!(weight.is_finite(), "forward decay weight overflows");
+        weight
+    }
 }
-impl Aggregate for Quantogram {
+impl<A: Aggregate> Aggregate for Decayed<A> {
     fn name(&self) -> &str {
-        "Quantogram"
+        &self.name
+    }
+    fn finalize(&mut self) {
+        self.aggregate.finalize();
     }
     fn get_quantil(&mut self, q: f64) -> f64 {
-        assert!(q >= 0f64 && q <= 1f64);
-        self.quantogram.quantile(q).unwrap()
+        self.aggregate.get_quantil(q)
     }
     fn insert(&mut self, value: f64) {
-        self.quantogram.add(value)
+        let weight = self.weight();
+        assert!(
+            self.aggregate.insert_weighted(value, weight),
+            "{} does not support weighted inserts",
+            self.aggregate.name()
+        );
     }
-
-    fn serialize_size(&self) -> usize {
-        0
+    fn negative_values(&self) -> NegativeValues {
+        self.aggregate.negative_values()
     }
 }
 
@@ -824,3 +4023,447 @@ impl DisplayWithUnderscores for usize {
             .join("_")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVALID_VALUES: [f64; 3] = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+
+    /// 1..=1000 with the invalid values spread in between.
+    fn values_with_invalid() -> Vec<f64> {
+        let mut values: Vec<f64> = (1..=1000).map(|value| value as f64).collect();
+        for (index, invalid) in INVALID_VALUES.iter().enumerate() {
+            values.insert(250 * (index + 1), *invalid);
+        }
+        values
+    }
+
+    #[test]
+    fn validated_rejects_invalid_values() {
+        let mut validated = Validated::new(AllValues::new(), InvalidValuePolicy::Reject);
+        assert_eq!(validated.try_insert(f64::NAN), Err(InvalidValue::NaN));
+        assert_eq!(
+            validated.try_insert(f64::INFINITY),
+            Err(InvalidValue::Infinity)
+        );
+        assert_eq!(
+            validated.try_insert(f64::NEG_INFINITY),
+            Err(InvalidValue::NegInfinity)
+        );
+        assert_eq!(validated.try_insert(1.0), Ok(()));
+        assert_eq!(validated.aggregate.values, vec![1.0]);
+        assert_eq!((validated.skipped, validated.clamped), (0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "rejected NaN")]
+    fn validated_insert_panics_on_rejected_value() {
+        Validated::new(AllValues::new(), InvalidValuePolicy::Reject).insert(f64::NAN);
+    }
+
+    #[test]
+    fn validated_skips_invalid_values() {
+        let mut validated = Validated::new(AllValues::new(), InvalidValuePolicy::Skip);
+        for value in values_with_invalid() {
+            assert_eq!(validated.try_insert(value), Ok(()));
+        }
+        assert_eq!((validated.skipped, validated.clamped), (3, 0));
+        assert_eq!(validated.aggregate.values.len(), 1000);
+        assert!(validated
+            .aggregate
+            .values
+            .iter()
+            .all(|value| value.is_finite()));
+    }
+
+    #[test]
+    fn validated_clamps_infinite_values() {
+        let policy = InvalidValuePolicy::Clamp {
+            min: 0.0,
+            max: 500.0,
+        };
+        let mut validated = Validated::new(AllValues::new(), policy);
+        for value in values_with_invalid() {
+            assert_eq!(validated.try_insert(value), Ok(()));
+        }
+        // NaN is skipped, infinity and the 500 values above `max` are clamped.
+        assert_eq!((validated.skipped, validated.clamped), (1, 502));
+        validated.finalize();
+        assert_eq!(validated.aggregate.values.len(), 1002);
+        assert_eq!(validated.aggregate.values[0], 0.0);
+        assert_eq!(*validated.aggregate.values.last().unwrap(), 500.0);
+    }
+
+    /// Inserts through `Validated` with every policy, no backend may see or panic on an invalid
+    /// value and the quantiles stay finite.
+    fn check_invalid_values<A: Aggregate, F: Fn() -> A>(aggregate: F) {
+        let policies = [
+            InvalidValuePolicy::Reject,
+            InvalidValuePolicy::Skip,
+            InvalidValuePolicy::Clamp {
+                min: 0.0,
+                max: 1e12,
+            },
+        ];
+        for policy in policies {
+            let mut validated = Validated::new(aggregate(), policy);
+            let mut rejected = 0;
+            for value in values_with_invalid() {
+                if validated.try_insert(value).is_err() {
+                    rejected += 1;
+                }
+            }
+            let expected_rejected = if policy == InvalidValuePolicy::Reject {
+                3
+            } else {
+                0
+            };
+            assert_eq!(
+                rejected,
+                expected_rejected,
+                "{} {}",
+                validated.name(),
+                policy
+            );
+            for percentile in validated.get_percentiles() {
+                assert!(
+                    percentile.value.is_finite(),
+                    "{} {}: {}",
+                    validated.name(),
+                    policy,
+                    percentile.value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_backend_panics_on_invalid_values() {
+        check_invalid_values(AllValues::new);
+        check_invalid_values(|| TDigest::new(500, 300));
+        check_invalid_values(|| HDRHistogram::new(2));
+        check_invalid_values(|| HDRScaledHistogram::auto(2));
+        check_invalid_values(|| HDRDoubleHistogram::new(40, 2));
+        check_invalid_values(DDSketch::new);
+        check_invalid_values(|| DDSketch2::unbounded(0.01));
+        check_invalid_values(Quantogram::new);
+        check_invalid_values(|| {
+            PromNativeHistogram::new(3, native_histogram::DEFAULT_ZERO_THRESHOLD)
+        });
+        check_invalid_values(Circllhist::new);
+        check_invalid_values(|| QDigest::new(100, 32));
+        check_invalid_values(|| Reservoir::new(100));
+        check_invalid_values(|| WeightedReservoir::new(100));
+        check_invalid_values(|| QuantilesGK::new(0.001));
+        check_invalid_values(|| QuantilesCKMS::new(0.001));
+        check_invalid_values(|| ZWQuantile::new(0.001));
+    }
+
+    /// Log-normal values like the simulated response times of `get_distributions`.
+    fn response_times(count: usize) -> Vec<f64> {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let distribution = rand_distr::LogNormal::new(2.996f64, 0.979f64).unwrap();
+        (0..count).map(|_| distribution.sample(&mut rng)).collect()
+    }
+
+    /// The percentiles of the deserialized aggregate and of the original.
+    fn round_trip<A: Aggregate>(aggregate: &mut A, values: &[f64]) -> (Percentiles, Percentiles) {
+        for value in values {
+            aggregate.insert(*value);
+        }
+        let expected = aggregate.get_percentiles();
+        let mut decoded = A::deserialize(&aggregate.serialize()).unwrap();
+        (decoded.get_percentiles(), expected)
+    }
+
+    fn assert_exact_round_trip<A: Aggregate>(mut aggregate: A, values: &[f64]) {
+        assert!(!aggregate.lossy_serialization(), "{}", aggregate.name());
+        let (decoded, expected) = round_trip(&mut aggregate, values);
+        assert_eq!(
+            max_relative_error(&decoded, &expected),
+            0.0,
+            "{}",
+            aggregate.name()
+        );
+    }
+
+    /// A bucket based sketch is restored into the same or a neighboring bucket.
+    fn assert_relative_round_trip<A: Aggregate>(mut aggregate: A, values: &[f64], error: f64) {
+        assert!(aggregate.lossy_serialization(), "{}", aggregate.name());
+        let (decoded, expected) = round_trip(&mut aggregate, values);
+        let deviation = max_relative_error(&decoded, &expected);
+        assert!(
+            deviation <= 2.0 * error,
+            "{}: {}",
+            aggregate.name(),
+            deviation
+        );
+    }
+
+    #[test]
+    fn exact_round_trips() {
+        let values = response_times(10_000);
+        assert_exact_round_trip(AllValues::new(), &values);
+        assert_exact_round_trip(QuantilesGK::new(0.001), &values);
+        assert_exact_round_trip(QuantilesCKMS::new(0.001), &values);
+        assert_exact_round_trip(TDigest::new(500, 300), &values);
+        assert_exact_round_trip(HDRHistogram::new(2), &values);
+        assert_exact_round_trip(HDRScaledHistogram::auto(2), &values);
+        assert_exact_round_trip(HDRDoubleHistogram::new(40, 2), &values);
+        assert_exact_round_trip(DDSketch::new(), &values);
+        assert_exact_round_trip(
+            PromNativeHistogram::new(3, native_histogram::DEFAULT_ZERO_THRESHOLD),
+            &values,
+        );
+        assert_exact_round_trip(
+            ClassicHistogram::new(classic_histogram::DEFAULT_BUCKETS.to_vec()),
+            &values,
+        );
+        assert_exact_round_trip(Circllhist::new(), &values);
+        assert_exact_round_trip(QDigest::new(100, 32), &values);
+        assert_exact_round_trip(Reservoir::new(1_000), &values);
+        assert_exact_round_trip(WeightedReservoir::new(1_000), &values);
+        assert_exact_round_trip(Mirrored::new(|| HDRHistogram::new(2)), &values);
+        assert_exact_round_trip(
+            Validated::new(Circllhist::new(), InvalidValuePolicy::Skip),
+            &values,
+        );
+    }
+
+    #[test]
+    fn lossy_round_trips() {
+        let values = response_times(10_000);
+        assert_relative_round_trip(DDSketch2::unbounded(0.01), &values, 0.01);
+        assert_relative_round_trip(Quantogram::new(), &values, QUANTOGRAM_ERROR);
+
+        // ZW has a rank error, the restored quantiles are compared with the exact ranks.
+        let epsilon = 0.001;
+        let mut zw = ZWQuantile::new(epsilon);
+        let (decoded, _) = round_trip(&mut zw, &values);
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        for (percentile, decoded) in SELECTED_PERCENTILES.iter().zip(decoded) {
+            let rank = sorted.partition_point(|value| *value < decoded.value) as f64;
+            let rank_error = (rank / sorted.len() as f64 - percentile / 100.0).abs();
+            assert!(
+                rank_error <= 2.0 * epsilon + 1.0 / sorted.len() as f64,
+                "{}: {}",
+                percentile,
+                rank_error
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_ddsketch2_round_trip() {
+        let values = response_times(10_000);
+        let mut sketch = DDSketch2::unbounded(0.01);
+        for (i, value) in values.iter().enumerate() {
+            // Later values weigh more, like in `Decayed`.
+            assert!(sketch.insert_weighted(*value, 1.0 + i as f64 / 1_000.0));
+        }
+        let expected = sketch.get_percentiles();
+        for encoding in [Encoding::Native, Encoding::Varint, Encoding::Protobuf] {
+            let bytes = sketch.encode(encoding).unwrap();
+            let mut decoded =
+                DDSketch2::<CubicallyInterpolatedMapping, UnboundedSizeDenseStore>::decode(
+                    encoding, &bytes,
+                )
+                .unwrap();
+            let deviation = max_relative_error(&decoded.get_percentiles(), &expected);
+            assert!(deviation <= 2.0 * 0.01, "{}: {}", encoding, deviation);
+            let relative_weight = (decoded.weight - sketch.weight).abs() / sketch.weight;
+            assert!(relative_weight < 1e-9, "{}: {}", encoding, decoded.weight);
+        }
+    }
+
+    /// DDSketch2 against the golden messages of `ddsketch_proto`, in both directions.
+    #[test]
+    fn ddsketch2_protobuf_fixtures() {
+        use ddsketch_proto::tests::{CUBIC, LOGARITHMIC, VALUES};
+
+        fn check<A: Aggregate>(mut sketch: A, fixture: &[u8]) {
+            for value in VALUES {
+                sketch.insert(value);
+            }
+            assert_eq!(sketch.encode(Encoding::Protobuf).unwrap(), fixture);
+            let mut decoded = A::decode(Encoding::Protobuf, fixture).unwrap();
+            assert_eq!(decoded.encode(Encoding::Protobuf).unwrap(), fixture);
+            let deviation =
+                max_relative_error(&decoded.get_percentiles(), &sketch.get_percentiles());
+            assert!(deviation <= 2.0 * 0.01, "{}: {}", sketch.name(), deviation);
+        }
+        check(DDSketch2::unbounded(0.01), CUBIC);
+        check(DDSketch2::logarithmic_low(0.01), LOGARITHMIC);
+    }
+
+    /// A message with the bins renumbered by an integer index offset decodes into the same
+    /// sketch, a fractional offset is rejected.
+    fn assert_index_offsets<A: Aggregate>(fixture: &[u8]) {
+        let mut expected = A::decode(Encoding::Protobuf, fixture).unwrap();
+        let mut proto = ddsketch_proto::DDSketch::decode(fixture).unwrap();
+        let shift = 3 - proto.mapping.index_offset as i32;
+        proto.mapping.index_offset += shift as f64;
+        proto.positive.index_offset += shift;
+        proto.negative.index_offset += shift;
+        let mut decoded = A::decode(Encoding::Protobuf, &proto.encode()).unwrap();
+        assert_eq!(
+            max_relative_error(&decoded.get_percentiles(), &expected.get_percentiles()),
+            0.0
+        );
+        proto.mapping.index_offset += 0.5;
+        assert!(A::decode(Encoding::Protobuf, &proto.encode()).is_err());
+    }
+
+    #[test]
+    fn ddsketch2_index_offset() {
+        assert_index_offsets::<DDSketch2<CubicallyInterpolatedMapping, UnboundedSizeDenseStore>>(
+            ddsketch_proto::tests::CUBIC,
+        );
+    }
+
+    #[test]
+    fn ddsketch_protobuf() {
+        use ddsketch_proto::tests::{LOGARITHMIC, VALUES};
+
+        let mut sketch = DDSketch::new();
+        for value in VALUES {
+            sketch.insert(value);
+        }
+        let encoded = sketch.encode(Encoding::Protobuf).unwrap();
+        let proto = ddsketch_proto::DDSketch::decode(&encoded).unwrap();
+        assert_eq!(proto.mapping.index_offset, 1.0);
+        let mut decoded = DDSketch::decode(Encoding::Protobuf, &encoded).unwrap();
+        assert_eq!(decoded.encode(Encoding::Protobuf).unwrap(), encoded);
+        assert_eq!(decoded.sketch.count(), VALUES.len());
+
+        // The fixture has index offset 0 and puts 1.0 into the next bin, the relative distance of
+        // two bins is gamma - 1.
+        let mut fixture = DDSketch::decode(Encoding::Protobuf, LOGARITHMIC).unwrap();
+        let deviation = max_relative_error(&fixture.get_percentiles(), &sketch.get_percentiles());
+        assert!(deviation <= 1.01 / 0.99 - 1.0 + 1e-9, "{}", deviation);
+        assert_index_offsets::<DDSketch>(LOGARITHMIC);
+        assert!(DDSketch::decode(Encoding::Protobuf, ddsketch_proto::tests::CUBIC).is_err());
+    }
+
+    #[test]
+    fn ddsketch_heap_size_counts_bins() {
+        let mut sketch = DDSketch::new();
+        assert_eq!(sketch.heap_size().unwrap().total(), 0);
+        for value in response_times(1_000) {
+            sketch.insert(value);
+        }
+        assert!(sketch.heap_size().unwrap().live > 0);
+    }
+
+    #[test]
+    fn tumbling_window_waits_for_first_rotation() {
+        let second = std::time::Duration::from_secs(1);
+        let mut windowed = Windowed::new(WindowKind::Tumbling, 10 * second, Circllhist::new);
+        for i in 0..10 {
+            windowed.advance(i * second);
+            windowed.insert(1.0);
+        }
+        assert!(windowed.window().is_none());
+        assert!(windowed.get_quantil(0.5).is_nan());
+        windowed.advance(10 * second);
+        windowed.insert(100.0);
+        // The complete window, without the value of the new one.
+        let window = windowed.window().unwrap();
+        assert!(window.get_quantil(1.0) < 2.0);
+    }
+
+    #[test]
+    fn windows_without_values() {
+        let second = std::time::Duration::from_secs(1);
+        let sliding = WindowKind::Sliding { slots: 4 };
+        for kind in [WindowKind::Tumbling, sliding] {
+            let mut windowed = Windowed::new(kind, 4 * second, Circllhist::new);
+            windowed.insert(1.0);
+            // Several windows pass without inserts.
+            windowed.advance(30 * second);
+            assert!(windowed.window().is_none(), "{}", kind);
+            assert!(windowed.get_quantil(0.5).is_nan());
+            // The current slot starts at the last slot boundary before `now`.
+            windowed.insert(2.0);
+            windowed.advance(32 * second);
+            assert_eq!(windowed.slot_start, 32 * second);
+            assert!((windowed.get_quantil(0.5) - 2.0).abs() < 0.1, "{}", kind);
+            // No overflow when more slots than fit in `u32` elapsed.
+            windowed.advance(std::time::Duration::from_secs(u64::MAX / 2));
+            assert!(windowed.window().is_none(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn sliding_window_merges_clones_of_slots() {
+        let second = std::time::Duration::from_secs(1);
+        let kind = WindowKind::Sliding { slots: 4 };
+        let mut windowed = Windowed::new(kind, 4 * second, Circllhist::new);
+        let mut expected = Circllhist::new();
+        for i in 0..8 {
+            windowed.advance(i * second);
+            windowed.insert(i as f64 + 1.0);
+            if i >= 4 {
+                expected.insert(i as f64 + 1.0);
+            }
+            // Queries must not consume the slots.
+            windowed.get_quantil(0.5);
+        }
+        assert_eq!(windowed.slots.len(), 4);
+        let deviation =
+            max_relative_error(&windowed.get_percentiles(), &expected.get_percentiles());
+        assert_eq!(deviation, 0.0);
+    }
+
+    #[test]
+    fn mirrored_encodes_both_sides() {
+        let mut mirrored = Mirrored::new(|| HDRHistogram::new(2));
+        for value in -100..=100 {
+            mirrored.insert(value as f64);
+        }
+        let encoded = mirrored.encode(Encoding::V2Deflate).unwrap();
+        let mut decoded = Mirrored::<HDRHistogram>::decode(Encoding::V2Deflate, &encoded).unwrap();
+        assert_eq!(
+            max_relative_error(&decoded.get_percentiles(), &mirrored.get_percentiles()),
+            0.0
+        );
+        assert!(Mirrored::new(AllValues::new)
+            .encode(Encoding::V2Deflate)
+            .is_none());
+    }
+
+    #[test]
+    fn mirrored_only_negative_values() {
+        let mut mirrored = Mirrored::new(AllValues::new);
+        for value in 1..=100 {
+            mirrored.insert(-value as f64);
+        }
+        mirrored.finalize();
+        assert_eq!(mirrored.get_quantil(0.0), -100.0);
+        assert_eq!(mirrored.get_quantil(1.0), -1.0);
+    }
+
+    #[test]
+    fn mirrored_only_positive_values() {
+        let mut mirrored = Mirrored::new(AllValues::new);
+        for value in 1..=100 {
+            mirrored.insert(value as f64);
+        }
+        mirrored.finalize();
+        assert_eq!(mirrored.get_quantil(0.0), 1.0);
+        assert_eq!(mirrored.get_quantil(1.0), 100.0);
+    }
+
+    #[test]
+    fn hdr_histogram_inserts_invalid_values() {
+        let mut hdr = HDRHistogram::new(2);
+        for value in values_with_invalid() {
+            hdr.insert(value);
+        }
+        for percentile in hdr.get_percentiles() {
+            assert!(percentile.value.is_finite());
+        }
+    }
+}
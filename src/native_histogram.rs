@@ -0,0 +1,350 @@
+//! Emulation of the Prometheus native (sparse) histogram.
+//!
+//! Buckets are exponential with `base = 2^(2^-schema)`. Bucket `i` covers `(base^(i-1), base^i]`
+//! for positive values, negative values are stored mirrored in their own bucket set. Values with
+//! an absolute value below or equal to the zero threshold land in the zero bucket.
+//!
+//! Quantile estimation follows `histogram_quantile` in PromQL.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{put_varint, put_zigzag, DecodeError, Reader};
+use crate::heap_size::HeapSize;
+
+/// Default zero threshold of the Prometheus client libraries (2^-128).
+pub const DEFAULT_ZERO_THRESHOLD: f64 = 2.938735877055719e-39;
+
+pub const MIN_SCHEMA: i32 = -4;
+pub const MAX_SCHEMA: i32 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NativeHistogram {
+    schema: i32,
+    zero_threshold: f64,
+    /// When set, the schema is reduced until the number of populated buckets fits, like the
+    /// `NativeHistogramMaxBucketNumber` option of client_golang.
+    max_buckets: Option<usize>,
+    zero_count: u64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl NativeHistogram {
+    pub fn new(schema: i32, zero_threshold: f64) -> Self {
+        assert!(
+            (MIN_SCHEMA..=MAX_SCHEMA).contains(&schema),
+            "schema must be in [{}, {}]",
+            MIN_SCHEMA,
+            MAX_SCHEMA
+        );
+        Self {
+            schema,
+            zero_threshold: zero_threshold.abs(),
+            max_buckets: None,
+            zero_count: 0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn with_max_buckets(mut self, max_buckets: usize) -> Self {
+        self.max_buckets = Some(max_buckets);
+        self
+    }
+
+    pub fn num_buckets(&self) -> usize {
+        self.positive.len() + self.negative.len()
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.sum += value;
+        let abs = value.abs();
+        if abs <= self.zero_threshold {
+            self.zero_count += 1;
+            return;
+        }
+        let key = bucket_index(abs, self.schema);
+        let buckets = if value > 0.0 {
+            &mut self.positive
+        } else {
+            &mut self.negative
+        };
+        *buckets.entry(key).or_insert(0) += 1;
+        self.limit_buckets();
+    }
+
+    fn limit_buckets(&mut self) {
+        if let Some(max_buckets) = self.max_buckets {
+            while self.num_buckets() > max_buckets && self.schema > MIN_SCHEMA {
+                self.reduce_schema(self.schema - 1);
+            }
+        }
+    }
+
+    /// Merges adjacent buckets until the histogram has the target schema.
+    fn reduce_schema(&mut self, target: i32) {
+        debug_assert!(target <= self.schema);
+        let shift = self.schema - target;
+        if shift == 0 {
+            return;
+        }
+        let reduce = |buckets: &BTreeMap<i32, u64>| {
+            let mut reduced = BTreeMap::new();
+            for (key, count) in buckets {
+                // Bucket `i` at schema `s` maps to `ceil(i / 2^shift)` at schema `s - shift`.
+                let new_key = ((*key as i64 + (1 << shift) - 1) >> shift) as i32;
+                *reduced.entry(new_key).or_insert(0) += count;
+            }
+            reduced
+        };
+        self.positive = reduce(&self.positive);
+        self.negative = reduce(&self.negative);
+        self.schema = target;
+    }
+
+    /// Widens the zero bucket, buckets that are completely covered are moved into it.
+    fn widen_zero_bucket(&mut self, zero_threshold: f64) {
+        if zero_threshold <= self.zero_threshold {
+            return;
+        }
+        let schema = self.schema;
+        let mut moved = 0;
+        for buckets in [&mut self.positive, &mut self.negative] {
+            buckets.retain(|key, count| {
+                if upper_bound(*key, schema) <= zero_threshold {
+                    moved += *count;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        self.zero_count += moved;
+        self.zero_threshold = zero_threshold;
+    }
+
+    /// Merges `other` into `self`. Differing schemas are resolved to the coarser schema and
+    /// differing zero thresholds to the wider zero bucket, same as Prometheus does.
+    pub fn merge(&mut self, other: &NativeHistogram) {
+        let mut other = other.clone();
+        let schema = self.schema.min(other.schema);
+        self.reduce_schema(schema);
+        other.reduce_schema(schema);
+        let zero_threshold = self.zero_threshold.max(other.zero_threshold);
+        self.widen_zero_bucket(zero_threshold);
+        other.widen_zero_bucket(zero_threshold);
+
+        self.zero_count += other.zero_count;
+        for (key, count) in other.positive {
+            *self.positive.entry(key).or_insert(0) += count;
+        }
+        for (key, count) in other.negative {
+            *self.negative.entry(key).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.limit_buckets();
+    }
+
+    /// All buckets in ascending value order as `(lower, upper, count)`.
+    fn buckets(&self) -> Vec<(f64, f64, u64)> {
+        let mut buckets = Vec::with_capacity(self.num_buckets() + 1);
+        for (key, count) in self.negative.iter().rev() {
+            buckets.push((
+                -upper_bound(*key, self.schema),
+                -upper_bound(*key - 1, self.schema),
+                *count,
+            ));
+        }
+        if self.zero_count > 0 {
+            // Like Prometheus, the zero bucket does not extend into the negative range if there
+            // are no negative observations.
+            let lower = if self.negative.is_empty() {
+                0.0
+            } else {
+                -self.zero_threshold
+            };
+            let upper = if self.positive.is_empty() && !self.negative.is_empty() {
+                0.0
+            } else {
+                self.zero_threshold
+            };
+            buckets.push((lower, upper, self.zero_count));
+        }
+        for (key, count) in self.positive.iter() {
+            buckets.push((
+                upper_bound(*key - 1, self.schema),
+                upper_bound(*key, self.schema),
+                *count,
+            ));
+        }
+        buckets
+    }
+
+    /// Estimates the quantile the same way PromQL's `histogram_quantile` does for native
+    /// histograms: the bucket is located by rank, within the bucket the value is interpolated
+    /// exponentially, except for the zero bucket which is interpolated linearly.
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 || q.is_nan() {
+            return f64::NAN;
+        }
+        if q < 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if q > 1.0 {
+            return f64::INFINITY;
+        }
+        let buckets = self.buckets();
+        let rank = q * self.count as f64;
+        let mut seen = 0u64;
+        let mut selected = buckets[buckets.len() - 1];
+        let mut rank_in_bucket = selected.2 as f64;
+        for bucket in buckets.iter() {
+            if (seen + bucket.2) as f64 >= rank {
+                selected = *bucket;
+                rank_in_bucket = rank - seen as f64;
+                break;
+            }
+            seen += bucket.2;
+        }
+        let (lower, upper, count) = selected;
+        let fraction = rank_in_bucket / count as f64;
+
+        if lower <= 0.0 && upper >= 0.0 {
+            // Zero bucket, linear interpolation.
+            return lower + (upper - lower) * fraction;
+        }
+        let log_lower = lower.abs().log2();
+        let log_upper = upper.abs().log2();
+        if lower > 0.0 {
+            (log_lower + (log_upper - log_lower) * fraction).exp2()
+        } else {
+            // Negative buckets are interpolated from the other end.
+            -(log_upper + (log_lower - log_upper) * (1.0 - fraction)).exp2()
+        }
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::btree_map(&self.positive) + HeapSize::btree_map(&self.negative)
+    }
+
+    /// Schema, zero threshold, max buckets (0 if unlimited), zero count, count and sum, followed
+    /// by the positive and the negative buckets as number of buckets and `(index, count)` pairs.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(48 + self.num_buckets() * 12);
+        out.extend_from_slice(&self.schema.to_le_bytes());
+        out.extend_from_slice(&self.zero_threshold.to_le_bytes());
+        out.extend_from_slice(&(self.max_buckets.unwrap_or(0) as u64).to_le_bytes());
+        out.extend_from_slice(&self.zero_count.to_le_bytes());
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&self.sum.to_le_bytes());
+        for buckets in [&self.positive, &self.negative] {
+            out.extend_from_slice(&(buckets.len() as u32).to_le_bytes());
+            for (index, count) in buckets.iter() {
+                out.extend_from_slice(&index.to_le_bytes());
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let schema = reader.i32()?;
+        if !(MIN_SCHEMA..=MAX_SCHEMA).contains(&schema) {
+            return Err(DecodeError::Invalid("schema"));
+        }
+        let mut histogram = NativeHistogram::new(schema, reader.f64()?);
+        histogram.max_buckets = match reader.u64()? {
+            0 => None,
+            max_buckets => Some(max_buckets as usize),
+        };
+        histogram.zero_count = reader.u64()?;
+        histogram.count = reader.u64()?;
+        histogram.sum = reader.f64()?;
+        for buckets in [&mut histogram.positive, &mut histogram.negative] {
+            for _ in 0..reader.u32()? {
+                buckets.insert(reader.i32()?, reader.u64()?);
+            }
+        }
+        reader.finish()?;
+        Ok(histogram)
+    }
+
+    /// Like `serialize`, but with varints. Bucket indices and counts are stored as zigzag
+    /// encoded deltas to the previous bucket, like the protobuf format of Prometheus.
+    pub fn serialize_varint(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_zigzag(&mut out, self.schema as i64);
+        out.extend_from_slice(&self.zero_threshold.to_le_bytes());
+        put_varint(&mut out, self.max_buckets.unwrap_or(0) as u64);
+        put_varint(&mut out, self.zero_count);
+        put_varint(&mut out, self.count);
+        out.extend_from_slice(&self.sum.to_le_bytes());
+        for buckets in [&self.positive, &self.negative] {
+            put_varint(&mut out, buckets.len() as u64);
+            let (mut previous_index, mut previous_count) = (0i64, 0i64);
+            for (index, count) in buckets.iter() {
+                put_zigzag(&mut out, *index as i64 - previous_index);
+                put_zigzag(&mut out, *count as i64 - previous_count);
+                previous_index = *index as i64;
+                previous_count = *count as i64;
+            }
+        }
+        out
+    }
+
+    pub fn deserialize_varint(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let schema = reader.zigzag()?;
+        if !(MIN_SCHEMA as i64..=MAX_SCHEMA as i64).contains(&schema) {
+            return Err(DecodeError::Invalid("schema"));
+        }
+        let mut histogram = NativeHistogram::new(schema as i32, reader.f64()?);
+        histogram.max_buckets = match reader.varint()? {
+            0 => None,
+            max_buckets => Some(max_buckets as usize),
+        };
+        histogram.zero_count = reader.varint()?;
+        histogram.count = reader.varint()?;
+        histogram.sum = reader.f64()?;
+        for buckets in [&mut histogram.positive, &mut histogram.negative] {
+            let (mut index, mut count) = (0i64, 0i64);
+            for _ in 0..reader.varint()? {
+                index += reader.zigzag()?;
+                count += reader.zigzag()?;
+                let index = i32::try_from(index).map_err(|_| DecodeError::Invalid("index"))?;
+                buckets.insert(index, count as u64);
+            }
+        }
+        reader.finish()?;
+        Ok(histogram)
+    }
+}
+
+/// Index of the bucket containing the positive value `abs`.
+fn bucket_index(abs: f64, schema: i32) -> i32 {
+    let key = (abs.log2() * 2f64.powi(schema)).ceil() as i32;
+    // Correct floating point imprecision of log2 at the bucket boundaries.
+    if upper_bound(key - 1, schema) >= abs {
+        key - 1
+    } else if upper_bound(key, schema) < abs {
+        key + 1
+    } else {
+        key
+    }
+}
+
+/// Upper bound of bucket `key`, `base^key` with `base = 2^(2^-schema)`.
+fn upper_bound(key: i32, schema: i32) -> f64 {
+    (key as f64 * 2f64.powi(-schema)).exp2()
+}
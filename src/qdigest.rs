@@ -0,0 +1,360 @@
+//! q-digest (Shrivastava et al., "Medians and Beyond: New Aggregation Techniques for Sensor
+//! Networks") for integer values in `[0, 2^universe_bits)`.
+//!
+//! The digest is a sparse complete binary tree over the universe, every node counts values in its
+//! range. Nodes are merged into their parent while the parent, the node and its sibling together
+//! hold no more than `n / compression` values. This bounds the rank error of a quantile query by
+//! `universe_bits / compression * n` and the number of nodes by `3 * compression`.
+//!
+//! The compression strategy and the serialization layout follow the widely used stream-lib
+//! implementation.
+use std::collections::{BinaryHeap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{put_varint, DecodeError, Reader};
+use crate::heap_size::HeapSize;
+
+/// Node ids go up to `2^(universe_bits + 1) - 1` and the upper end of a range is computed as
+/// `2^(universe_bits + 1)` before subtracting, both have to fit into a `u64`.
+pub const MAX_UNIVERSE_BITS: u32 = 62;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    UniverseMismatch,
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::UniverseMismatch => write!(f, "universe sizes differ"),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QDigest {
+    compression: u64,
+    universe_bits: u32,
+    /// Node id to count. The root has id 1, the children of node `n` are `2n` and `2n + 1`, the
+    /// leaf for value `v` is `v + 2^universe_bits`.
+    nodes: HashMap<u64, u64>,
+    count: u64,
+}
+
+impl QDigest {
+    pub fn new(compression: u64, universe_bits: u32) -> Self {
+        assert!(compression > 0, "compression must be positive");
+        assert!(
+            (1..=MAX_UNIVERSE_BITS).contains(&universe_bits),
+            "universe_bits must be in [1, {}]",
+            MAX_UNIVERSE_BITS
+        );
+        Self {
+            compression,
+            universe_bits,
+            nodes: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        1 << self.universe_bits
+    }
+
+    fn max_value(&self) -> u64 {
+        self.capacity() - 1
+    }
+
+    /// Values above the universe are clamped to its maximum.
+    pub fn insert(&mut self, value: u64) {
+        let leaf = value.min(self.max_value()) + self.capacity();
+        *self.nodes.entry(leaf).or_insert(0) += 1;
+        self.count += 1;
+        self.compress_upward(leaf);
+        if self.nodes.len() as u64 > 3 * self.compression {
+            self.compress_fully();
+        }
+    }
+
+    fn get(&self, node: u64) -> u64 {
+        self.nodes.get(&node).copied().unwrap_or(0)
+    }
+
+    fn threshold(&self) -> u64 {
+        self.count / self.compression
+    }
+
+    fn is_leaf(&self, node: u64) -> bool {
+        node >= self.capacity()
+    }
+
+    /// Moves `node` and its sibling into the parent.
+    fn merge_into_parent(&mut self, node: u64) {
+        let moved =
+            self.nodes.remove(&node).unwrap_or(0) + self.nodes.remove(&(node ^ 1)).unwrap_or(0);
+        *self.nodes.entry(node / 2).or_insert(0) += moved;
+    }
+
+    fn compress_upward(&mut self, mut node: u64) {
+        let threshold = self.threshold();
+        let mut at_node = self.get(node);
+        while node != 1 {
+            if at_node > threshold {
+                break;
+            }
+            let at_sibling = self.get(node ^ 1);
+            if at_node + at_sibling > threshold {
+                break;
+            }
+            let at_parent = self.get(node / 2);
+            if at_node + at_sibling + at_parent > threshold {
+                break;
+            }
+            self.merge_into_parent(node);
+            node /= 2;
+            at_node += at_sibling + at_parent;
+        }
+    }
+
+    /// Visits the nodes bottom-up, by descending id, until no node, its sibling and its parent can
+    /// be merged anymore. This restores the q-digest property for every node, which is what
+    /// bounds the digest to `3 * compression` nodes.
+    fn compress_fully(&mut self) {
+        let threshold = self.threshold();
+        let mut pending: BinaryHeap<u64> = self.nodes.keys().copied().collect();
+        while let Some(node) = pending.pop() {
+            if node == 1 {
+                continue;
+            }
+            let at_node = self.get(node);
+            let at_sibling = self.get(node ^ 1);
+            if at_node == 0 && at_sibling == 0 {
+                continue;
+            }
+            let at_parent = self.get(node / 2);
+            if at_node + at_sibling + at_parent > threshold {
+                continue;
+            }
+            self.merge_into_parent(node);
+            // The parent grew and may merge further up, the children of the node and its sibling
+            // lost their parent count and may be compressible now.
+            pending.push(node / 2);
+            if !self.is_leaf(node) {
+                pending.push(node * 2);
+                pending.push((node ^ 1) * 2);
+            }
+        }
+    }
+
+    /// Range of values `[left, right]` covered by `node`.
+    fn range(&self, node: u64) -> (u64, u64) {
+        let depth = 63 - node.leading_zeros();
+        let height = self.universe_bits - depth;
+        let left = (node << height) - self.capacity();
+        let right = ((node + 1) << height) - 1 - self.capacity();
+        (left, right)
+    }
+
+    pub fn merge(&mut self, other: &QDigest) -> Result<(), MergeError> {
+        if self.universe_bits != other.universe_bits {
+            return Err(MergeError::UniverseMismatch);
+        }
+        for (node, count) in other.nodes.iter() {
+            *self.nodes.entry(*node).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.compress_fully();
+        Ok(())
+    }
+
+    /// The nodes are ordered by the upper end of their range, smaller ranges first. The
+    /// quantile is the upper end of the first node at which the cumulative count exceeds the
+    /// rank.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let mut ranges: Vec<(u64, u64, u64)> = self
+            .nodes
+            .iter()
+            .map(|(node, count)| {
+                let (left, right) = self.range(*node);
+                (left, right, *count)
+            })
+            .collect();
+        ranges.sort_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+        let rank = q * self.count as f64;
+        let mut seen = 0;
+        for (_, right, count) in ranges.iter() {
+            seen += count;
+            if seen as f64 > rank {
+                return Some(*right);
+            }
+        }
+        ranges.last().map(|(_, right, _)| *right)
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::hash_map(&self.nodes)
+    }
+
+    /// stream-lib layout: count, compression, capacity, number of nodes and the nodes as
+    /// `(id, count)` pairs, all big-endian.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(28 + self.nodes.len() * 16);
+        out.extend_from_slice(&self.count.to_be_bytes());
+        out.extend_from_slice(&self.compression.to_be_bytes());
+        out.extend_from_slice(&self.capacity().to_be_bytes());
+        out.extend_from_slice(&(self.nodes.len() as u32).to_be_bytes());
+        for (node, count) in self.nodes.iter() {
+            out.extend_from_slice(&node.to_be_bytes());
+            out.extend_from_slice(&count.to_be_bytes());
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let count = reader.u64_be()?;
+        let compression = reader.u64_be()?;
+        let capacity = reader.u64_be()?;
+        let universe_bits = capacity.trailing_zeros();
+        if compression == 0
+            || !capacity.is_power_of_two()
+            || !(1..=MAX_UNIVERSE_BITS).contains(&universe_bits)
+        {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut digest = QDigest::new(compression, universe_bits);
+        digest.count = count;
+        let num_nodes = reader.u32_be()?;
+        for _ in 0..num_nodes {
+            let node = reader.u64_be()?;
+            if node == 0 || node >= 2 * capacity {
+                return Err(DecodeError::Invalid("node"));
+            }
+            digest.nodes.insert(node, reader.u64_be()?);
+        }
+        reader.finish()?;
+        Ok(digest)
+    }
+
+    /// Count, compression, universe bits, number of nodes and the nodes sorted by id, ids as
+    /// deltas, all as varints.
+    pub fn serialize_varint(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varint(&mut out, self.count);
+        put_varint(&mut out, self.compression);
+        out.push(self.universe_bits as u8);
+        let mut nodes: Vec<(u64, u64)> = self.nodes.iter().map(|(n, c)| (*n, *c)).collect();
+        nodes.sort_unstable();
+        put_varint(&mut out, nodes.len() as u64);
+        let mut previous = 0;
+        for (node, count) in nodes {
+            put_varint(&mut out, node - previous);
+            put_varint(&mut out, count);
+            previous = node;
+        }
+        out
+    }
+
+    pub fn deserialize_varint(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let count = reader.varint()?;
+        let compression = reader.varint()?;
+        let universe_bits = reader.u8()? as u32;
+        if compression == 0 || !(1..=MAX_UNIVERSE_BITS).contains(&universe_bits) {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut digest = QDigest::new(compression, universe_bits);
+        digest.count = count;
+        let mut node = 0u64;
+        for _ in 0..reader.varint()? {
+            node = node.wrapping_add(reader.varint()?);
+            if node == 0 || node >= 2 * digest.capacity() {
+                return Err(DecodeError::Invalid("node"));
+            }
+            digest.nodes.insert(node, reader.varint()?);
+        }
+        reader.finish()?;
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn compression_bounds_nodes() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let mut digest = QDigest::new(100, 32);
+        for _ in 0..100_000 {
+            digest.insert(rng.gen_range(0..1 << 32));
+            assert!(digest.nodes.len() <= 3 * 100 + 1);
+        }
+        digest.compress_fully();
+        assert!(digest.nodes.len() <= 3 * 100);
+    }
+
+    #[test]
+    fn rank_error_within_bound() {
+        let mut rng = StdRng::from_seed([1u8; 32]);
+        let mut values: Vec<u64> = (0..50_000).map(|_| rng.gen_range(0..1 << 20)).collect();
+        let mut digest = QDigest::new(200, 20);
+        for value in values.iter() {
+            digest.insert(*value);
+        }
+        values.sort_unstable();
+        let max_rank_error = 20.0 / 200.0 * values.len() as f64;
+        for q in [0.01, 0.1, 0.5, 0.9, 0.99] {
+            let estimate = digest.quantile(q).unwrap();
+            let rank = values.partition_point(|value| *value <= estimate) as f64;
+            let expected = q * values.len() as f64;
+            assert!(
+                (rank - expected).abs() <= max_rank_error,
+                "q {q}: rank {rank}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn max_universe_width() {
+        let mut digest = QDigest::new(10, MAX_UNIVERSE_BITS);
+        assert_eq!(digest.range(1), (0, (1 << MAX_UNIVERSE_BITS) - 1));
+        let max_leaf = digest.max_value() + digest.capacity();
+        assert_eq!(
+            digest.range(max_leaf),
+            (digest.max_value(), digest.max_value())
+        );
+        for value in [0, 1, 1 << 40, u64::MAX] {
+            for _ in 0..100 {
+                digest.insert(value);
+            }
+        }
+        assert_eq!(digest.quantile(1.0), Some(digest.max_value()));
+
+        let decoded = QDigest::deserialize(&digest.serialize()).unwrap();
+        assert_eq!(decoded.nodes, digest.nodes);
+        let decoded = QDigest::deserialize_varint(&digest.serialize_varint()).unwrap();
+        assert_eq!(decoded.nodes, digest.nodes);
+    }
+
+    #[test]
+    #[should_panic]
+    fn universe_above_max_is_rejected() {
+        QDigest::new(10, MAX_UNIVERSE_BITS + 1);
+    }
+
+    #[test]
+    fn deserialize_rejects_universe_above_max() {
+        let mut data = QDigest::new(10, MAX_UNIVERSE_BITS).serialize();
+        data[16..24].copy_from_slice(&(1u64 << 63).to_be_bytes());
+        assert!(QDigest::deserialize(&data).is_err());
+    }
+}
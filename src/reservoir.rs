@@ -0,0 +1,265 @@
+//! Fixed-size random samples of a stream. Quantiles are read from the sorted sample.
+//!
+//! - `Reservoir`: Vitter's Algorithm R, every value has the same probability to be in the sample.
+//! - `WeightedReservoir`: A-Res (Efraimidis and Spirakis), every value gets the random key
+//!   `u^(1/weight)` and the values with the largest keys are kept. Since the keys don't depend on
+//!   the other values, two samples merge exactly by keeping the largest keys of both.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ordered_float::OrderedFloat;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{put_sorted_f64s, put_varint, DecodeError, Reader};
+use crate::heap_size::HeapSize;
+
+/// Every reservoir gets its own seed, so partial samples that are merged are independent, while
+/// runs stay reproducible.
+fn next_rng() -> Pcg64Mcg {
+    static SEED: AtomicU64 = AtomicU64::new(1);
+    Pcg64Mcg::seed_from_u64(SEED.fetch_add(1, Ordering::Relaxed))
+}
+
+fn quantile_of_sorted(values: &[f64], q: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let index = (values.len() as f64 * q).ceil() as usize;
+    Some(values[index.min(values.len() - 1)])
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reservoir {
+    capacity: usize,
+    samples: Vec<f64>,
+    count: u64,
+    sorted: bool,
+    #[serde(skip, default = "next_rng")]
+    rng: Pcg64Mcg,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            capacity,
+            samples: Vec::new(),
+            count: 0,
+            sorted: true,
+            rng: next_rng(),
+        }
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        self.count += 1;
+        if self.samples.len() < self.capacity {
+            self.samples.push(value);
+        } else {
+            let index = self.rng.gen_range(0..self.count);
+            if index < self.capacity as u64 {
+                self.samples[index as usize] = value;
+            }
+        }
+        self.sorted = false;
+    }
+
+    /// Every sampled value of a reservoir stands for `count / samples` values of its stream. The
+    /// merged sample is drawn from the union with these weights (A-Res), which keeps the
+    /// inclusion probability proportional to the original stream sizes.
+    pub fn merge(&mut self, others: &[Reservoir]) {
+        let mut weighted = WeightedReservoir::new(self.capacity);
+        weighted.rng = self.rng.clone();
+        for reservoir in std::iter::once(&*self).chain(others.iter()) {
+            if reservoir.samples.is_empty() {
+                continue;
+            }
+            let weight = reservoir.count as f64 / reservoir.samples.len() as f64;
+            for value in reservoir.samples.iter() {
+                weighted.insert_weighted(*value, weight);
+            }
+        }
+        self.count += others.iter().map(|reservoir| reservoir.count).sum::<u64>();
+        self.samples = weighted
+            .heap
+            .into_iter()
+            .map(|entry| entry.0 .1 .0)
+            .collect();
+        self.rng = weighted.rng;
+        self.sorted = false;
+    }
+
+    fn sort(&mut self) {
+        if !self.sorted {
+            self.samples.sort_by(|a, b| a.total_cmp(b));
+            self.sorted = true;
+        }
+    }
+
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.sort();
+        quantile_of_sorted(&self.samples, q)
+    }
+
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::vec(&self.samples)
+    }
+
+    /// Capacity, count, number of samples and the samples. The random state is not serialized,
+    /// a deserialized reservoir continues with a new seed.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(20 + self.samples.len() * 8);
+        out.extend_from_slice(&(self.capacity as u64).to_le_bytes());
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&(self.samples.len() as u32).to_le_bytes());
+        for value in self.samples.iter() {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let capacity = reader.u64()? as usize;
+        let count = reader.u64()?;
+        let num_samples = reader.u32()? as usize;
+        if capacity == 0 || num_samples > capacity || num_samples as u64 > count {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut reservoir = Reservoir::new(capacity);
+        reservoir.count = count;
+        reservoir.samples = (0..num_samples)
+            .map(|_| reader.f64())
+            .collect::<Result<_, _>>()?;
+        reservoir.sorted = false;
+        reader.finish()?;
+        Ok(reservoir)
+    }
+
+    /// Capacity and count as varints, followed by the samples sorted and delta encoded.
+    pub fn serialize_varint(&mut self) -> Vec<u8> {
+        self.sort();
+        let mut out = Vec::new();
+        put_varint(&mut out, self.capacity as u64);
+        put_varint(&mut out, self.count);
+        put_sorted_f64s(&mut out, &self.samples);
+        out
+    }
+
+    pub fn deserialize_varint(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let capacity = reader.varint()? as usize;
+        let count = reader.varint()?;
+        let samples = reader.sorted_f64s()?;
+        if capacity == 0 || samples.len() > capacity || samples.len() as u64 > count {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut reservoir = Reservoir::new(capacity);
+        reservoir.count = count;
+        reservoir.samples = samples;
+        reader.finish()?;
+        Ok(reservoir)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedReservoir {
+    capacity: usize,
+    /// Min-heap on the key, so the sample with the smallest key is evicted first.
+    heap: BinaryHeap<Reverse<(OrderedFloat<f64>, OrderedFloat<f64>)>>,
+    #[serde(skip)]
+    sorted: Vec<f64>,
+    #[serde(skip, default = "next_rng")]
+    rng: Pcg64Mcg,
+}
+
+impl WeightedReservoir {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity + 1),
+            sorted: Vec::new(),
+            rng: next_rng(),
+        }
+    }
+
+    pub fn insert_weighted(&mut self, value: f64, weight: f64) {
+        if weight <= 0.0 || !weight.is_finite() {
+            return;
+        }
+        // `ln(u) / weight` orders the same as `u^(1/weight)`, but doesn't underflow.
+        let u: f64 = self.rng.gen_range(f64::MIN_POSITIVE..1.0);
+        self.push(OrderedFloat(u.ln() / weight), value);
+    }
+
+    fn push(&mut self, key: OrderedFloat<f64>, value: f64) {
+        if self.heap.len() < self.capacity {
+            self.heap.push(Reverse((key, OrderedFloat(value))));
+        } else if key > self.heap.peek().unwrap().0 .0 {
+            self.heap.pop();
+            self.heap.push(Reverse((key, OrderedFloat(value))));
+        } else {
+            return;
+        }
+        self.sorted.clear();
+    }
+
+    /// Keeps the largest keys of all samples. If the capacities differ, the result has the
+    /// capacity of `self`.
+    pub fn merge(&mut self, others: Vec<WeightedReservoir>) {
+        for other in others {
+            for Reverse((key, value)) in other.heap {
+                self.push(key, value.0);
+            }
+        }
+    }
+
+    /// Unweighted quantile of the sample. A-Res samples without replacement, so heavy values are
+    /// under-represented once single weights are a noticeable share of the total, and this only
+    /// approximates the weighted quantile of the inserted values.
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        if self.sorted.is_empty() {
+            self.sorted = self.heap.iter().map(|entry| entry.0 .1 .0).collect();
+            self.sorted.sort_by(|a, b| a.total_cmp(b));
+        }
+        quantile_of_sorted(&self.sorted, q)
+    }
+
+    /// Includes the sorted copy of the samples kept for queries.
+    pub fn heap_size(&self) -> HeapSize {
+        HeapSize::binary_heap(&self.heap) + HeapSize::vec(&self.sorted)
+    }
+
+    /// Capacity, number of samples and the samples as `(key, value)` pairs. Keeping the keys
+    /// makes merges after a round trip exact.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12 + self.heap.len() * 16);
+        out.extend_from_slice(&(self.capacity as u64).to_le_bytes());
+        out.extend_from_slice(&(self.heap.len() as u32).to_le_bytes());
+        for Reverse((key, value)) in self.heap.iter() {
+            out.extend_from_slice(&key.0.to_le_bytes());
+            out.extend_from_slice(&value.0.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(data);
+        let capacity = reader.u64()? as usize;
+        let num_samples = reader.u32()? as usize;
+        if capacity == 0 || num_samples > capacity {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut reservoir = WeightedReservoir::new(capacity);
+        for _ in 0..num_samples {
+            let key = reader.f64()?;
+            reservoir.push(OrderedFloat(key), reader.f64()?);
+        }
+        reader.finish()?;
+        Ok(reservoir)
+    }
+}
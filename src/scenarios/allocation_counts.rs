@@ -0,0 +1,94 @@
+//! Allocator calls per phase, to find algorithms that allocate in the hot path.
+
+use prettytable::{row, Cell, Row};
+
+use super::Backends;
+use crate::tracking_alloc::AllocCounts;
+use crate::{
+    get_distributions, get_markdown_table, pretty_print_count, Aggregate, Sampler, GLOBAL,
+};
+
+/// Counts the allocator calls per phase, to find algorithms that allocate in the hot path.
+#[allow(dead_code)]
+pub fn test_allocation_counts() {
+    let count_group = (0..10).map(|_| 100_000).collect::<Vec<_>>();
+    let backends = Backends::default();
+
+    for (distr, distribution) in &mut get_distributions() {
+        println!("\nCOUNT={}", pretty_print_count(&count_group));
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Insert (alloc / realloc / free)",
+            "Per 1k Inserts",
+            "Finalize",
+            "Merge",
+            "Query",
+            "Calls/s",
+        ]);
+
+        for_each_backend!(backends, |aggregate| allocation_counts(
+            &count_group,
+            aggregate,
+            distribution,
+            table.add_row(row![distr])
+        ));
+        table.printstd();
+    }
+}
+
+/// Fills one aggregate per count and merges them, like `run_test`. The insert phase includes the
+/// calls of the sampler, `Calls/s` covers all phases.
+fn allocation_counts<A: Aggregate, F: Fn() -> A>(
+    count_group: &[usize],
+    aggregate: F,
+    sampler: &mut Sampler,
+    row: &mut Row,
+) {
+    let mut aggregates = Vec::with_capacity(count_group.len());
+    let mut insert = AllocCounts::default();
+    let mut finalize = AllocCounts::default();
+    let start = std::time::Instant::now();
+    for count in count_group {
+        let before = GLOBAL.counts();
+        let mut aggregate = aggregate();
+        for i in 0..*count {
+            aggregate.insert(sampler(i));
+        }
+        let inserted = GLOBAL.counts();
+        aggregate.finalize();
+        let finalized = GLOBAL.counts();
+        insert = insert + (inserted - before);
+        finalize = finalize + (finalized - inserted);
+        aggregates.push(aggregate);
+    }
+    let name = aggregates[0].name().to_string();
+
+    let before = GLOBAL.counts();
+    let merged = A::merge(aggregates);
+    let merge = GLOBAL.counts() - before;
+    let Some(mut merged) = merged else {
+        row.add_cell(Cell::new(&name));
+        row.add_cell(Cell::new("merge is not supported"));
+        return;
+    };
+    let before = GLOBAL.counts();
+    merged.get_percentiles();
+    let query = GLOBAL.counts() - before;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let inserts: usize = count_group.iter().sum();
+    let total = insert.total() + finalize.total() + merge.total() + query.total();
+    for entry in [
+        name,
+        insert.to_string(),
+        format!("{:.1}", insert.total() as f64 * 1000.0 / inserts as f64),
+        finalize.to_string(),
+        merge.to_string(),
+        query.to_string(),
+        format!("{:.0}", total as f64 / elapsed),
+    ] {
+        row.add_cell(Cell::new(&entry));
+    }
+}
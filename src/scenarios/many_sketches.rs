@@ -0,0 +1,100 @@
+//! One small aggregate per key, like a sketch per (endpoint, status, region).
+
+use itertools::Itertools;
+use prettytable::{row, Cell, Row};
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::Distribution;
+
+use super::Backends;
+use crate::{
+    get_distributions, get_markdown_table, Aggregate, DisplayWithUnderscores, Sampler, GLOBAL,
+};
+
+/// One aggregate per key with few values each, like a sketch per (endpoint, status, region). The
+/// values are assigned to the keys by a Zipf distribution, so a few keys get most values and
+/// most keys only a handful. `parallel-collect` only interleaves the aggregates of a count group.
+#[allow(dead_code)]
+pub fn test_many_sketches() {
+    let keys = 100_000;
+    let count = 2_000_000;
+    let zipf_exponent = 1.1;
+    let backends = Backends {
+        reservoir_size: 1_000,
+        ..Backends::default()
+    };
+
+    let zipf = rand_distr::Zipf::new(keys as u64, zipf_exponent).unwrap();
+    let mut rng = StdRng::from_seed([1u8; 32]);
+    let assignment: Vec<u32> = (0..count)
+        .map(|_| zipf.sample(&mut rng) as u32 - 1)
+        .collect();
+    let used_keys = assignment.iter().unique().count();
+    let hottest_key_count = assignment.iter().filter(|key| **key == 0).count();
+
+    for (distr, distribution) in &mut get_distributions() {
+        println!(
+            "\nKEYS={}, COUNT={}, ZIPF_EXPONENT={}, USED_KEYS={}, HOTTEST_KEY_COUNT={}, RESERVOIR_SIZE={}",
+            keys.separate_with_underscores(),
+            count.separate_with_underscores(),
+            zipf_exponent,
+            used_keys.separate_with_underscores(),
+            hottest_key_count.separate_with_underscores(),
+            backends.reservoir_size.separate_with_underscores(),
+        );
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Values/s",
+            "Empty",
+            "Total",
+            "PeakMemory",
+            "Per Sketch",
+            "Per Value",
+        ]);
+
+        for_each_backend!(backends, |aggregate| many_sketches(
+            keys,
+            &assignment,
+            aggregate,
+            distribution,
+            table.add_row(row![distr])
+        ));
+        table.printstd();
+    }
+}
+
+/// Creates all aggregates upfront and inserts the values into the aggregate of their key. The
+/// memory includes the aggregates themselves, as they would be stored in a map.
+fn many_sketches<A: Aggregate, F: Fn() -> A>(
+    keys: usize,
+    assignment: &[u32],
+    aggregate: F,
+    sampler: &mut Sampler,
+    row: &mut Row,
+) {
+    let allocated_before = GLOBAL.allocated();
+    GLOBAL.reset_peak_memory();
+    let mut aggregates: Vec<A> = (0..keys).map(|_| aggregate()).collect();
+    let empty = GLOBAL.allocated().saturating_sub(allocated_before);
+
+    let start = std::time::Instant::now();
+    for (i, key) in assignment.iter().enumerate() {
+        aggregates[*key as usize].insert(sampler(i));
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let total = GLOBAL.allocated().saturating_sub(allocated_before);
+    let peak_memory = GLOBAL.get_peak_memory();
+
+    for entry in [
+        aggregates[0].name().to_string(),
+        format!("{:.0}", assignment.len() as f64 / elapsed),
+        format!("{}k", empty / 1024),
+        format!("{}k", total / 1024),
+        format!("{}k", peak_memory / 1024),
+        format!("{}", total / keys),
+        format!("{:.1}", total as f64 / assignment.len() as f64),
+    ] {
+        row.add_cell(Cell::new(&entry));
+    }
+}
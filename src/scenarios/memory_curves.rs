@@ -0,0 +1,140 @@
+//! Allocated bytes while the aggregates are filled, as table and chart.
+
+use itertools::Itertools;
+use prettytable::row;
+use serde::Serialize;
+
+use super::{Backend, Backends};
+use crate::{chart, get_distributions, get_markdown_table, Aggregate, DisplayWithUnderscores};
+use crate::{Sampler, GLOBAL};
+
+/// Samples the allocated bytes while the aggregates are filled, to show how they grow and shrink
+/// during ingestion. Writes the curves to `memory_curves.json` and `memory_curves.svg`.
+#[allow(dead_code)]
+pub fn test_memory_curves() {
+    let count = 1_000_000;
+    let interval = 10_000;
+    // AllValues is left out, it would dwarf the other curves.
+    let backends = Backends {
+        skip: &[Backend::AllValues],
+        ..Backends::default()
+    };
+
+    let mut curves = Vec::new();
+    let mut charts = Vec::new();
+    for (distr, distribution) in &mut get_distributions() {
+        let mut distr_curves = Vec::new();
+        for_each_backend!(backends, |aggregate| distr_curves.push(memory_curve(
+            distr,
+            count,
+            interval,
+            aggregate,
+            distribution
+        )));
+
+        println!(
+            "\nCOUNT={}, INTERVAL={}",
+            count.separate_with_underscores(),
+            interval.separate_with_underscores()
+        );
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Peak",
+            "Before Finalize",
+            "Finalized",
+            "Largest Drop",
+        ]);
+        for curve in &distr_curves {
+            let peak = curve.samples.iter().max().copied().unwrap_or(0);
+            let largest_drop = curve
+                .samples
+                .iter()
+                .tuple_windows()
+                .map(|(before, after)| before.saturating_sub(*after))
+                .max()
+                .unwrap_or(0);
+            table.add_row(row![
+                distr,
+                curve.algorithm,
+                format!("{}k", peak / 1024),
+                format!("{}k", curve.samples.last().copied().unwrap_or(0) / 1024),
+                format!("{}k", curve.finalized / 1024),
+                format!("{}k", largest_drop / 1024),
+            ]);
+        }
+        table.printstd();
+
+        let series = distr_curves
+            .iter()
+            .map(|curve| chart::Series {
+                name: curve.algorithm.clone(),
+                points: curve
+                    .samples
+                    .iter()
+                    .enumerate()
+                    .map(|(index, bytes)| ((index * interval) as f64, *bytes as f64 / 1024.0))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        charts.push(chart::line_chart(
+            distr,
+            "Inserts",
+            "Allocated (KiB)",
+            &series,
+        ));
+        curves.extend(distr_curves);
+    }
+
+    std::fs::write(
+        "memory_curves.json",
+        serde_json::to_vec_pretty(&curves).unwrap(),
+    )
+    .unwrap();
+    std::fs::write("memory_curves.svg", chart::stack(&charts)).unwrap();
+    println!("\nWrote memory_curves.json and memory_curves.svg");
+}
+
+/// Allocated bytes of one aggregate while it is filled.
+#[derive(Serialize)]
+struct MemoryCurve {
+    distribution: String,
+    algorithm: String,
+    interval: usize,
+    /// Bytes after 0, `interval`, 2 * `interval`, ... inserts, the last sample is taken after
+    /// all values.
+    samples: Vec<usize>,
+    /// Bytes after `finalize`.
+    finalized: usize,
+}
+
+/// The samples are preallocated, so the allocator delta only covers the aggregate. Allocations of
+/// the sampler are included.
+fn memory_curve<A: Aggregate, F: Fn() -> A>(
+    distribution_name: &str,
+    count: usize,
+    interval: usize,
+    aggregate: F,
+    sampler: &mut Sampler,
+) -> MemoryCurve {
+    let mut samples = Vec::with_capacity(count / interval + 2);
+    let allocated_before = GLOBAL.allocated();
+    let mut aggregate = aggregate();
+    for i in 0..count {
+        if i % interval == 0 {
+            samples.push(GLOBAL.allocated().saturating_sub(allocated_before));
+        }
+        aggregate.insert(sampler(i));
+    }
+    samples.push(GLOBAL.allocated().saturating_sub(allocated_before));
+    aggregate.finalize();
+    let finalized = GLOBAL.allocated().saturating_sub(allocated_before);
+    MemoryCurve {
+        distribution: distribution_name.to_string(),
+        algorithm: aggregate.name().to_string(),
+        interval,
+        samples,
+        finalized,
+    }
+}
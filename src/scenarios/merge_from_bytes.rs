@@ -0,0 +1,111 @@
+//! Deserialize and merge throughput of many partial sketches, like a query that reads them
+//! from storage.
+
+use prettytable::{row, Cell, Row};
+
+use super::Backends;
+use crate::{
+    get_distributions, get_markdown_table, pretty_print_count, pretty_print_ser_size, Sampler,
+};
+use crate::{Aggregate, GLOBAL};
+
+/// Query path of a storage system: many partial sketches are read as bytes, deserialized and
+/// merged. Measures the throughput and peak memory of deserialize + merge.
+#[allow(dead_code)]
+pub fn test_merge_from_bytes() {
+    let counts = [
+        (0..1000).map(|_| 1000).collect::<Vec<_>>(),
+        (0..10_000).map(|_| 100).collect::<Vec<_>>(),
+    ];
+    let backends = Backends::default();
+
+    let mut distributions = get_distributions();
+
+    for (distr, distribution) in &mut distributions {
+        for count_group in counts.iter() {
+            let mut table = get_markdown_table();
+            println!("\nCOUNT={}", pretty_print_count(count_group));
+            table.set_titles(row![
+                "Distribution",
+                "Algorithm",
+                "Bytes",
+                "Deserialize",
+                "Merge",
+                "Sketches/s",
+                "MB/s",
+                "PeakMemory",
+                "99.0"
+            ]);
+
+            for_each_backend!(backends, |aggregate| merge_from_bytes(
+                count_group,
+                aggregate,
+                distribution,
+                table.add_row(row![distr])
+            ));
+            table.printstd();
+        }
+    }
+}
+
+/// Serializes one sketch per count, then deserializes and merges all of them. Only the second
+/// part is measured.
+fn merge_from_bytes<A: Aggregate, F: Fn() -> A>(
+    count_group: &[usize],
+    aggregate: F,
+    sampler: &mut Sampler,
+    row: &mut Row,
+) {
+    let serialized: Vec<Vec<u8>> = count_group
+        .iter()
+        .map(|count| {
+            let mut aggregate = aggregate();
+            for i in 0..*count {
+                aggregate.insert(sampler(i));
+            }
+            aggregate.finalize();
+            aggregate.serialize()
+        })
+        .collect();
+    let mut name = aggregate().name().to_string();
+    // Lossy formats are restored by replaying their values, the throughput is not comparable.
+    if aggregate().lossy_serialization() {
+        name.push_str(" (lossy)");
+    }
+    let bytes: usize = serialized.iter().map(|data| data.len()).sum();
+    if bytes == 0 {
+        row.add_cell(Cell::new(&name));
+        row.add_cell(Cell::new("unavailable"));
+        return;
+    }
+
+    GLOBAL.reset_peak_memory();
+    let start = std::time::Instant::now();
+    let aggregates: Vec<A> = serialized
+        .iter()
+        .map(|data| A::deserialize(data).unwrap())
+        .collect();
+    let deserialize_time = start.elapsed();
+    let start = std::time::Instant::now();
+    let merged = A::merge(aggregates);
+    let merge_time = start.elapsed();
+    let peak_memory = GLOBAL.get_peak_memory();
+
+    let total = (deserialize_time + merge_time).as_secs_f64();
+    let p99 = match merged {
+        Some(mut merged) => format!("{:.2}", merged.get_quantil(0.99)),
+        None => "NaN".to_string(),
+    };
+    for entry in [
+        name,
+        pretty_print_ser_size(bytes),
+        format!("{:.3}s", deserialize_time.as_secs_f64()),
+        format!("{:.3}s", merge_time.as_secs_f64()),
+        format!("{:.0}", count_group.len() as f64 / total),
+        format!("{:.1}", bytes as f64 / total / 1_000_000.0),
+        format!("{}k ", peak_memory / 1024),
+        p99,
+    ] {
+        row.add_cell(Cell::new(&entry));
+    }
+}
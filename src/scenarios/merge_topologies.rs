@@ -0,0 +1,223 @@
+//! Error of partial aggregates merged in different orders, level by level.
+
+use itertools::Itertools;
+use prettytable::{row, Table};
+
+use super::Backends;
+use crate::DisplayWithUnderscores;
+use crate::{get_distributions, get_markdown_table, max_relative_error, Aggregate, AllValues};
+
+/// Merges the partial aggregates of a day of minutes in different topologies and reports the
+/// error against the exact percentiles after each level. `test` merges all partial aggregates at
+/// once, but the error of e.g. TDigest compounds differently across repeated merges.
+#[allow(dead_code)]
+pub fn test_merge_topologies() {
+    let leaves = 1440;
+    let leaf_count = 1000;
+    let topologies = [
+        MergeTopology::Linear { report_every: 360 },
+        MergeTopology::BalancedTree,
+        MergeTopology::Hierarchical(&[("hour", 60), ("day", 24)]),
+    ];
+    let backends = Backends::default();
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..leaves * leaf_count).map(&mut *distribution).collect();
+        println!(
+            "\nLEAVES={}, LEAF_COUNT={}",
+            leaves.separate_with_underscores(),
+            leaf_count.separate_with_underscores()
+        );
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Topology",
+            "Level",
+            "Nodes",
+            "Max Error",
+            "Mean Error",
+        ]);
+        for_each_backend!(backends, |aggregate| merge_topologies(
+            &topologies,
+            &values,
+            leaf_count,
+            aggregate,
+            distr,
+            &mut table
+        ));
+        table.printstd();
+    }
+}
+
+/// The order in which partial aggregates are merged.
+enum MergeTopology {
+    /// Merges the leaves one by one into an accumulator, reported every `report_every` leaves.
+    Linear { report_every: usize },
+    /// Merges pairs of neighbours until one aggregate is left.
+    BalancedTree,
+    /// Merges groups of the previous level at once, e.g. 60 minutes into an hour.
+    Hierarchical(&'static [(&'static str, usize)]),
+}
+
+impl std::fmt::Display for MergeTopology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeTopology::Linear { .. } => write!(f, "linear"),
+            MergeTopology::BalancedTree => write!(f, "balanced tree"),
+            MergeTopology::Hierarchical(levels) => {
+                write!(f, "{}", levels.iter().map(|(name, _)| name).join(" → "))
+            }
+        }
+    }
+}
+
+/// An aggregate and the range of values it covers.
+struct MergeNode<A> {
+    aggregate: A,
+    values: std::ops::Range<usize>,
+}
+
+/// `None` if the aggregate does not support merge.
+fn merge_nodes<A: Aggregate>(nodes: Vec<MergeNode<A>>) -> Option<MergeNode<A>> {
+    let values = nodes.first()?.values.start..nodes.last()?.values.end;
+    let aggregate = A::merge(nodes.into_iter().map(|node| node.aggregate).collect())?;
+    Some(MergeNode { aggregate, values })
+}
+
+/// Adds a row for the leaves and one per level of each topology.
+fn merge_topologies<A: Aggregate, F: Fn() -> A>(
+    topologies: &[MergeTopology],
+    values: &[f64],
+    leaf_count: usize,
+    aggregate: F,
+    distr: &str,
+    table: &mut Table,
+) {
+    let leaves = || {
+        values
+            .chunks(leaf_count)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut leaf = aggregate();
+                for value in chunk {
+                    leaf.insert(*value);
+                }
+                leaf.finalize();
+                MergeNode {
+                    aggregate: leaf,
+                    values: index * leaf_count..index * leaf_count + chunk.len(),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut nodes = leaves();
+    let name = nodes[0].aggregate.name().to_string();
+    let add_row = |table: &mut Table, topology: &str, level: &str, nodes: &mut [MergeNode<A>]| {
+        let (max_error, mean_error) = level_errors(nodes, values);
+        table.add_row(row![
+            distr,
+            name,
+            topology,
+            level,
+            nodes.len(),
+            format!("{:.4}%", max_error * 100.0),
+            format!("{:.4}%", mean_error * 100.0),
+        ]);
+    };
+    add_row(table, "-", "leaves", &mut nodes);
+
+    for topology in topologies {
+        let topology_name = topology.to_string();
+        let merged = match topology {
+            MergeTopology::Linear { report_every } => {
+                let mut nodes = leaves().into_iter();
+                let leaf_total = nodes.len();
+                let mut merged = nodes.next();
+                let mut folded = 1;
+                for node in nodes {
+                    merged = merge_nodes(vec![merged.unwrap(), node]);
+                    let Some(accumulator) = merged.as_mut() else {
+                        break;
+                    };
+                    folded += 1;
+                    if folded % report_every == 0 || folded == leaf_total {
+                        let level = format!("{} leaves", folded);
+                        add_row(
+                            table,
+                            &topology_name,
+                            &level,
+                            std::slice::from_mut(accumulator),
+                        );
+                    }
+                }
+                merged.is_some()
+            }
+            MergeTopology::BalancedTree => {
+                let mut nodes = leaves();
+                let mut level = 0;
+                let mut supported = true;
+                while nodes.len() > 1 {
+                    level += 1;
+                    let mut next = Vec::with_capacity(nodes.len().div_ceil(2));
+                    let mut iter = nodes.into_iter();
+                    while let Some(first) = iter.next() {
+                        match iter.next() {
+                            Some(second) => next.push(merge_nodes(vec![first, second])),
+                            None => next.push(Some(first)),
+                        }
+                    }
+                    let Some(next) = next.into_iter().collect::<Option<Vec<_>>>() else {
+                        supported = false;
+                        break;
+                    };
+                    nodes = next;
+                    add_row(
+                        table,
+                        &topology_name,
+                        &format!("level {}", level),
+                        &mut nodes,
+                    );
+                }
+                supported
+            }
+            MergeTopology::Hierarchical(levels) => {
+                let mut nodes = leaves();
+                let mut supported = true;
+                for (level, fan_out) in levels.iter() {
+                    let groups = nodes.into_iter().chunks(*fan_out);
+                    let next = groups
+                        .into_iter()
+                        .map(|group| merge_nodes(group.collect()))
+                        .collect::<Option<Vec<_>>>();
+                    let Some(next) = next else {
+                        supported = false;
+                        break;
+                    };
+                    nodes = next;
+                    add_row(table, &topology_name, level, &mut nodes);
+                }
+                supported
+            }
+        };
+        if !merged {
+            table.add_row(row![distr, name, topology_name, "merge is not supported"]);
+        }
+    }
+}
+
+/// Largest and mean error of the nodes against the exact percentiles of the values they cover.
+fn level_errors<A: Aggregate>(nodes: &mut [MergeNode<A>], values: &[f64]) -> (f64, f64) {
+    let errors: Vec<f64> = nodes
+        .iter_mut()
+        .map(|node| {
+            let mut exact = AllValues::new();
+            for value in &values[node.values.clone()] {
+                exact.insert(*value);
+            }
+            max_relative_error(&node.aggregate.get_percentiles(), &exact.get_percentiles())
+        })
+        .collect();
+    let max_error = errors.iter().cloned().fold(0f64, f64::max);
+    (max_error, errors.iter().sum::<f64>() / errors.len() as f64)
+}
@@ -0,0 +1,203 @@
+//! Merges of aggregates created with different parameters. The pairs of configurations are
+//! specific to each backend, so this scenario does not use `for_each_backend!`.
+
+use prettytable::{row, Table};
+
+use crate::ZWQuantile;
+use crate::{classic_histogram, get_distributions, get_markdown_table, max_relative_error};
+use crate::{native_histogram, panic_message, Aggregate, AllValues, DisplayWithUnderscores};
+use crate::{ClassicHistogram, DDSketch, DDSketch2, HDRDoubleHistogram, HDRHistogram};
+use crate::{HDRScaledHistogram, PromNativeHistogram, QDigest, QuantilesGK, Reservoir, TDigest};
+
+/// Merges aggregates with different parameters, as happens during a rolling config change. Each
+/// pair is merged in both orders, since most backends keep the parameters of one side. The error
+/// of the mixed merges is compared to merges with equal parameters: "adapted" if it is not worse
+/// than the coarser config, "accuracy loss" if it is, "rejected" if the merge fails or panics.
+#[allow(dead_code)]
+pub fn test_mixed_config_merge() {
+    let count = 200_000;
+    let classic_buckets = classic_histogram::DEFAULT_BUCKETS.to_vec();
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..count).map(&mut *distribution).collect();
+        println!("\nCOUNT={}", count.separate_with_underscores());
+        let mut table = get_markdown_table();
+        table.set_titles(row![
+            "Distribution",
+            "Algorithm",
+            "Config A",
+            "Config B",
+            "A+A",
+            "B+B",
+            "A+B",
+            "B+A",
+            "Outcome",
+        ]);
+        let distr: &str = distr;
+        mixed_merge(
+            &values,
+            ("error 0.001", || QuantilesGK::new(0.001)),
+            ("error 0.01", || QuantilesGK::new(0.01)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("max_size 300", || TDigest::new(500, 300)),
+            ("max_size 100", || TDigest::new(500, 100)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("error 0.001", || ZWQuantile::new(0.001)),
+            ("error 0.01", || ZWQuantile::new(0.01)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("sigfig 3", || HDRHistogram::new(3)),
+            ("sigfig 2", || HDRHistogram::new(2)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("scale 1000", || HDRScaledHistogram::with_scale(2, 1000.0)),
+            ("scale 10", || HDRScaledHistogram::with_scale(2, 10.0)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("sigfig 3", || HDRDoubleHistogram::new(40, 3)),
+            ("sigfig 2", || HDRDoubleHistogram::new(40, 2)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("alpha 0.01", || DDSketch::with_alpha(0.01)),
+            ("alpha 0.02", || DDSketch::with_alpha(0.02)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("error 0.01", || DDSketch2::unbounded(0.01)),
+            ("error 0.02", || DDSketch2::unbounded(0.02)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("schema 5", || {
+                PromNativeHistogram::new(5, native_histogram::DEFAULT_ZERO_THRESHOLD)
+            }),
+            ("schema 3", || {
+                PromNativeHistogram::new(3, native_histogram::DEFAULT_ZERO_THRESHOLD)
+            }),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("default buckets", || {
+                ClassicHistogram::new(classic_buckets.clone())
+            }),
+            ("exponential 1.5", || {
+                ClassicHistogram::exponential(0.001, 1.5, 40)
+            }),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("compression 1000", || QDigest::new(1000, 32)),
+            ("compression 100", || QDigest::new(100, 32)),
+            distr,
+            &mut table,
+        );
+        mixed_merge(
+            &values,
+            ("size 10_000", || Reservoir::new(10_000)),
+            ("size 1_000", || Reservoir::new(1_000)),
+            distr,
+            &mut table,
+        );
+        table.printstd();
+    }
+}
+
+/// The first half of the values goes into the first aggregate of a merge, the second half into
+/// the second one.
+fn mixed_merge<A: Aggregate>(
+    values: &[f64],
+    (label_a, config_a): (&str, impl Fn() -> A),
+    (label_b, config_b): (&str, impl Fn() -> A),
+    distr: &str,
+    table: &mut Table,
+) {
+    let mut exact = AllValues::new();
+    for value in values {
+        exact.insert(*value);
+    }
+    let exact = exact.get_percentiles();
+    let (first_half, second_half) = values.split_at(values.len() / 2);
+    let fill = |config: &dyn Fn() -> A, values: &[f64]| {
+        let mut aggregate = config();
+        for value in values {
+            aggregate.insert(*value);
+        }
+        aggregate.finalize();
+        aggregate
+    };
+    let merge = |first: &dyn Fn() -> A, second: &dyn Fn() -> A| -> Result<f64, String> {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let aggregates = vec![fill(first, first_half), fill(second, second_half)];
+            A::merge(aggregates)
+                .map(|mut merged| max_relative_error(&merged.get_percentiles(), &exact))
+        }));
+        match result {
+            Ok(Some(error)) => Ok(error),
+            Ok(None) => Err("merge is not supported".to_string()),
+            Err(payload) => Err(format!("panic: {}", panic_message(&payload))),
+        }
+    };
+    let same_a = merge(&config_a, &config_a);
+    let same_b = merge(&config_b, &config_b);
+    let a_b = merge(&config_a, &config_b);
+    let b_a = merge(&config_b, &config_a);
+
+    let outcome = match (&same_a, &same_b, &a_b, &b_a) {
+        (Ok(same_a), Ok(same_b), Ok(a_b), Ok(b_a)) => {
+            let coarser = same_a.max(*same_b);
+            let worst = a_b.max(*b_a);
+            // A small tolerance for backends with randomized merges.
+            if worst <= coarser * 1.1 {
+                "adapted".to_string()
+            } else {
+                format!("accuracy loss ({:.1}x)", worst / coarser)
+            }
+        }
+        (Ok(_), Ok(_), Err(err), _) | (Ok(_), Ok(_), _, Err(err)) => format!("rejected ({})", err),
+        _ => "merge fails with equal configs".to_string(),
+    };
+    let describe = |result: &Result<f64, String>| match result {
+        Ok(error) => format!("{:.4}%", error * 100.0),
+        Err(_) => "FAILED".to_string(),
+    };
+    let name = config_a().name().to_string();
+    table.add_row(row![
+        distr,
+        name,
+        label_a,
+        label_b,
+        describe(&same_a),
+        describe(&same_b),
+        describe(&a_b),
+        describe(&b_a),
+        outcome,
+    ]);
+}
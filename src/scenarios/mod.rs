@@ -0,0 +1,129 @@
+//! Scenarios beyond the accuracy tables of `main`, one module each. Most run every backend of
+//! `for_each_backend!` with the parameters of `Backends`.
+
+/// A backend of `for_each_backend!`, to leave it out of a scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    AllValues,
+    QuantilesGK,
+    TDigest,
+    ZWQuantile,
+    HDRHistogram,
+    HDRScaled,
+    HDRDouble,
+    DDSketch,
+    DDSketch2,
+    Quantogram,
+    PromNative,
+    ClassicHistogram,
+    Circllhist,
+    QDigest,
+    Reservoir,
+}
+
+/// The parameters of the backends, shared by the scenarios, and the backends a scenario leaves
+/// out.
+pub struct Backends {
+    pub gk_error: f64,
+    pub zw_error: f64,
+    pub hdr_sigfig: u8,
+    pub hdr_range_bits: u32,
+    pub tdigest_batch: usize,
+    pub tdigest_max_size: usize,
+    pub dd2_error: f64,
+    pub prom_schema: i32,
+    pub qdigest_compression: u64,
+    pub qdigest_universe_bits: u32,
+    pub reservoir_size: usize,
+    pub skip: &'static [Backend],
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Self {
+            gk_error: 0.001,
+            zw_error: 0.001,
+            hdr_sigfig: 2,
+            hdr_range_bits: 40,
+            tdigest_batch: 500,
+            tdigest_max_size: 300,
+            dd2_error: 0.01,
+            prom_schema: 3,
+            qdigest_compression: 100,
+            qdigest_universe_bits: 32,
+            reservoir_size: 10_000,
+            skip: &[],
+        }
+    }
+}
+
+impl Backends {
+    pub fn includes(&self, backend: Backend) -> bool {
+        !self.skip.contains(&backend)
+    }
+}
+
+/// Evaluates `$body` once per backend of `$backends` that is not skipped, with `$aggregate` bound
+/// to the constructor of the backend. The HDR histograms are mirrored, the normal distribution
+/// has a few negative values, which they clamp.
+macro_rules! for_each_backend {
+    ($backends:expr, |$aggregate:ident| $body:expr) => {{
+        use $crate::scenarios::Backend;
+        let backends: &$crate::scenarios::Backends = &$backends;
+        for_each_backend!(@each backends, $aggregate, $body,
+            Backend::AllValues => || $crate::AllValues::new(),
+            Backend::QuantilesGK => || $crate::QuantilesGK::new(backends.gk_error),
+            Backend::TDigest => || {
+                $crate::TDigest::new(backends.tdigest_batch, backends.tdigest_max_size)
+            },
+            Backend::ZWQuantile => || $crate::ZWQuantile::new(backends.zw_error),
+            Backend::HDRHistogram => || {
+                $crate::Mirrored::new(|| $crate::HDRHistogram::new(backends.hdr_sigfig))
+            },
+            Backend::HDRScaled => || {
+                $crate::Mirrored::new(|| $crate::HDRScaledHistogram::auto(backends.hdr_sigfig))
+            },
+            Backend::HDRDouble => || {
+                $crate::Mirrored::new(|| {
+                    $crate::HDRDoubleHistogram::new(backends.hdr_range_bits, backends.hdr_sigfig)
+                })
+            },
+            Backend::DDSketch => || $crate::DDSketch::new(),
+            Backend::DDSketch2 => || $crate::DDSketch2::unbounded(backends.dd2_error),
+            Backend::Quantogram => || $crate::Quantogram::new(),
+            Backend::PromNative => || {
+                $crate::PromNativeHistogram::new(
+                    backends.prom_schema,
+                    $crate::native_histogram::DEFAULT_ZERO_THRESHOLD,
+                )
+            },
+            Backend::ClassicHistogram => || {
+                $crate::ClassicHistogram::new(
+                    $crate::classic_histogram::DEFAULT_BUCKETS.to_vec(),
+                )
+            },
+            Backend::Circllhist => || $crate::Circllhist::new(),
+            Backend::QDigest => || {
+                $crate::QDigest::new(backends.qdigest_compression, backends.qdigest_universe_bits)
+            },
+            Backend::Reservoir => || $crate::Reservoir::new(backends.reservoir_size),
+        )
+    }};
+    (@each $backends:ident, $aggregate:ident, $body:expr,
+        $($backend:expr => $constructor:expr,)*) => {{
+        $(
+            if $backends.includes($backend) {
+                let $aggregate = $constructor;
+                $body;
+            }
+        )*
+    }};
+}
+
+pub mod allocation_counts;
+pub mod many_sketches;
+pub mod memory_curves;
+pub mod merge_from_bytes;
+pub mod merge_topologies;
+pub mod mixed_config_merge;
+pub mod serialize_merge_cycles;
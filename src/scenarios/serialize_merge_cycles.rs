@@ -0,0 +1,139 @@
+//! Repeated decode, merge and encode of a stored aggregate, per encoding.
+
+use prettytable::{row, Cell, Table};
+
+use super::{Backend, Backends};
+use crate::{describe_deviation, get_distributions, get_markdown_table, max_relative_error};
+use crate::{pretty_print_ser_size, Aggregate, AllValues, DisplayWithUnderscores, Encoding};
+
+/// Stores an aggregate encoded and in every round decodes it, merges the aggregate of new values
+/// and encodes it again, like a pipeline that rolls sketches up in storage. Reports the error
+/// against the exact percentiles at some rounds, and the deviation from the same merges without
+/// encoding, which shows the loss of the encoding, e.g. floats in JSON.
+#[allow(dead_code)]
+pub fn test_serialize_merge_cycles() {
+    let rounds = 100;
+    let round_count = 10_000;
+    let reported_rounds = [1, 10, 100];
+    // Reservoirs are left out, their merge samples randomly, so there is no stable reference.
+    let backends = Backends {
+        skip: &[Backend::Reservoir],
+        ..Backends::default()
+    };
+
+    for (distr, distribution) in &mut get_distributions() {
+        let values: Vec<f64> = (0..rounds * round_count).map(&mut *distribution).collect();
+        println!(
+            "\nROUNDS={}, ROUND_COUNT={}",
+            rounds,
+            round_count.separate_with_underscores()
+        );
+        let mut table = get_markdown_table();
+        let mut titles = row!["Distribution", "Algorithm", "Encoding", "Size"];
+        for round in reported_rounds {
+            titles.add_cell(Cell::new(&format!("Error Round {}", round)));
+        }
+        titles.add_cell(Cell::new("Drift"));
+        table.set_titles(titles);
+
+        for_each_backend!(backends, |aggregate| serialize_merge_cycles(
+            &values,
+            round_count,
+            &reported_rounds,
+            aggregate,
+            distr,
+            &mut table
+        ));
+        table.printstd();
+    }
+}
+
+/// One row per supported encoding. The reference is merged the same way without encoding.
+fn serialize_merge_cycles<A: Aggregate, F: Fn() -> A>(
+    values: &[f64],
+    round_count: usize,
+    reported_rounds: &[usize],
+    aggregate: F,
+    distr: &str,
+    table: &mut Table,
+) {
+    let fill = |chunk: &[f64]| {
+        let mut aggregate = aggregate();
+        for value in chunk {
+            aggregate.insert(*value);
+        }
+        aggregate.finalize();
+        aggregate
+    };
+    let mut first = fill(&values[..round_count]);
+    let name = first.name().to_string();
+    for encoding in [
+        Encoding::Native,
+        Encoding::Json,
+        Encoding::Bincode,
+        Encoding::Varint,
+        Encoding::Protobuf,
+        Encoding::V2Deflate,
+    ] {
+        if first.encode(encoding).is_none() {
+            continue;
+        }
+        let row = table.add_row(row![distr, name, encoding]);
+        match serialize_merge_rounds(values, round_count, reported_rounds, encoding, fill) {
+            Ok(cells) => {
+                for cell in cells {
+                    row.add_cell(Cell::new(&cell));
+                }
+            }
+            Err(err) => {
+                row.add_cell(Cell::new(&format!("FAILED: {}", err)));
+            }
+        }
+    }
+}
+
+/// The cells of `serialize_merge_cycles`: the final size, the errors of the reported rounds and
+/// the drift.
+fn serialize_merge_rounds<A: Aggregate>(
+    values: &[f64],
+    round_count: usize,
+    reported_rounds: &[usize],
+    encoding: Encoding,
+    fill: impl Fn(&[f64]) -> A,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut reference: Option<A> = None;
+    let mut stored: Option<Vec<u8>> = None;
+    let mut errors = Vec::new();
+    for (round, chunk) in values.chunks(round_count).enumerate() {
+        let round = round + 1;
+        let merged = match stored {
+            Some(bytes) => A::merge(vec![A::decode(encoding, &bytes)?, fill(chunk)]),
+            None => Some(fill(chunk)),
+        };
+        let mut merged = merged.ok_or("merge is not supported")?;
+        stored = Some(merged.encode(encoding).ok_or("encoding is not supported")?);
+        reference = match reference {
+            Some(reference) => A::merge(vec![reference, fill(chunk)]),
+            None => Some(fill(chunk)),
+        };
+        if reported_rounds.contains(&round) {
+            let mut exact = AllValues::new();
+            for value in &values[..round * round_count] {
+                exact.insert(*value);
+            }
+            let mut decoded = A::decode(encoding, stored.as_ref().unwrap())?;
+            let error = max_relative_error(&decoded.get_percentiles(), &exact.get_percentiles());
+            errors.push(format!("{:.4}%", error * 100.0));
+        }
+    }
+    let stored = stored.ok_or("no values")?;
+    let mut decoded = A::decode(encoding, &stored)?;
+    let drift = describe_deviation(
+        &decoded.get_percentiles(),
+        &reference.unwrap().get_percentiles(),
+    );
+    let mut cells = vec![pretty_print_ser_size(stored.len())];
+    cells.extend(errors);
+    cells.push(drift);
+    Ok(cells)
+}
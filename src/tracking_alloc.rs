@@ -0,0 +1,134 @@
+//! Wraps the peak memory allocator and tracks the currently allocated bytes and the number of
+//! allocator calls, which `peakmem_alloc` does not expose.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct TrackingAlloc<A: 'static> {
+    inner: &'static A,
+    allocated: AtomicUsize,
+    allocations: AtomicUsize,
+    reallocations: AtomicUsize,
+    frees: AtomicUsize,
+}
+
+/// Number of allocator calls since the start of the program, the difference of two snapshots
+/// covers a phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocCounts {
+    pub allocations: usize,
+    pub reallocations: usize,
+    pub frees: usize,
+}
+
+impl AllocCounts {
+    pub fn total(&self) -> usize {
+        self.allocations + self.reallocations + self.frees
+    }
+}
+
+impl std::ops::Sub for AllocCounts {
+    type Output = AllocCounts;
+
+    fn sub(self, other: AllocCounts) -> AllocCounts {
+        AllocCounts {
+            allocations: self.allocations - other.allocations,
+            reallocations: self.reallocations - other.reallocations,
+            frees: self.frees - other.frees,
+        }
+    }
+}
+
+impl std::ops::Add for AllocCounts {
+    type Output = AllocCounts;
+
+    fn add(self, other: AllocCounts) -> AllocCounts {
+        AllocCounts {
+            allocations: self.allocations + other.allocations,
+            reallocations: self.reallocations + other.reallocations,
+            frees: self.frees + other.frees,
+        }
+    }
+}
+
+/// Allocations / reallocations / frees.
+impl std::fmt::Display for AllocCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} / {} / {}",
+            self.allocations, self.reallocations, self.frees
+        )
+    }
+}
+
+impl<A> TrackingAlloc<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        Self {
+            inner,
+            allocated: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            reallocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+        }
+    }
+
+    /// Bytes currently allocated, over all threads.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Allocator calls so far, over all threads.
+    pub fn counts(&self) -> AllocCounts {
+        AllocCounts {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            reallocations: self.reallocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Gives access to the peak memory of the wrapped allocator.
+impl<A> std::ops::Deref for TrackingAlloc<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.allocated.fetch_add(layout.size(), Ordering::Relaxed);
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.allocated.fetch_add(new_size, Ordering::Relaxed);
+            self.allocated.fetch_sub(layout.size(), Ordering::Relaxed);
+            self.reallocations.fetch_add(1, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}